@@ -0,0 +1,278 @@
+// Interrupt moderation: delay timers, interrupt throttling and adaptive ITR
+// [E1000 3.2.7, 3.4, 13.4.17, 13.4.19]
+use super::e1000::{E1000Device, KernelFunc};
+use super::e1000_const::*;
+use super::error::E1000Error;
+use super::link::LinkSpeed;
+use super::regs::E1000Regs;
+use core::cmp::{max, min};
+
+/// Fewest interrupts per second a fixed ITR takes
+pub const E1000_MIN_ITR_RATE: u32 = 100;
+/// Most interrupts per second a fixed ITR takes
+pub const E1000_MAX_ITR_RATE: u32 = 100000;
+
+/// Throttling of the interrupt rate by ITR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Itr {
+    /// No throttling, as many interrupts as the delay timers give
+    Off,
+    /// At most this many interrupts per second
+    Fixed(u32),
+    /// Retuned on every interrupt from the packets and bytes since the last one,
+    /// between 4000 and 70000 interrupts per second
+    Adaptive,
+}
+
+/// Builder of the interrupt moderation settings, passed to `E1000Device::set_coalesce`.
+/// Delays are in units of 1.024 µs, 0 turning the timer off.
+///
+/// ```ignore
+/// dev.set_coalesce(CoalesceConfig::new().rx_abs_delay(64).tx_delay(16).itr(Itr::Adaptive))?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoalesceConfig {
+    pub(super) rx_delay: u16,
+    pub(super) rx_abs_delay: u16,
+    pub(super) tx_delay: u16,
+    pub(super) tx_abs_delay: u16,
+    pub(super) itr: Itr,
+}
+
+impl Default for CoalesceConfig {
+    fn default() -> Self {
+        CoalesceConfig {
+            rx_delay: 0,
+            rx_abs_delay: 0,
+            tx_delay: 0,
+            tx_abs_delay: 0,
+            itr: Itr::Off,
+        }
+    }
+}
+
+impl CoalesceConfig {
+    /// One interrupt per packet: no delay and no throttling
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delay of the receive interrupt after a packet, restarted by each packet (RDTR)
+    pub fn rx_delay(mut self, delay: u16) -> Self {
+        self.rx_delay = delay;
+        self
+    }
+
+    /// Longest delay of the receive interrupt after the first packet (RADV)
+    pub fn rx_abs_delay(mut self, delay: u16) -> Self {
+        self.rx_abs_delay = delay;
+        self
+    }
+
+    /// Delay of the transmit interrupt after a packet is sent, restarted by each one (TIDV)
+    pub fn tx_delay(mut self, delay: u16) -> Self {
+        self.tx_delay = delay;
+        self
+    }
+
+    /// Longest delay of the transmit interrupt after the first packet sent (TADV)
+    pub fn tx_abs_delay(mut self, delay: u16) -> Self {
+        self.tx_abs_delay = delay;
+        self
+    }
+
+    /// Throttling of the interrupt rate
+    pub fn itr(mut self, itr: Itr) -> Self {
+        self.itr = itr;
+        self
+    }
+}
+
+/// Latency classes of the adaptive ITR, as in Linux e1000
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum ItrLatency {
+    /// 70000 interrupts per second
+    Lowest,
+    /// 20000 interrupts per second
+    Low,
+    /// 4000 interrupts per second
+    Bulk,
+}
+
+impl ItrLatency {
+    fn rate(self) -> u32 {
+        match self {
+            ItrLatency::Lowest => 70000,
+            ItrLatency::Low => 20000,
+            ItrLatency::Bulk => 4000,
+        }
+    }
+
+    /// The class for the next interval, from the packets and bytes of the last one
+    fn update(self, packets: usize, bytes: usize) -> Self {
+        if packets == 0 {
+            return self;
+        }
+        let per_packet = bytes / packets;
+        match self {
+            ItrLatency::Lowest => {
+                // jumbo frames get bulk treatment
+                if per_packet > 8000 {
+                    ItrLatency::Bulk
+                } else if packets < 5 && bytes > 512 {
+                    ItrLatency::Low
+                } else {
+                    self
+                }
+            }
+            ItrLatency::Low => {
+                if bytes > 10000 {
+                    if per_packet > 8000 || packets < 10 || per_packet > 1200 {
+                        ItrLatency::Bulk
+                    } else if packets > 35 {
+                        ItrLatency::Lowest
+                    } else {
+                        self
+                    }
+                } else if per_packet > 2000 {
+                    ItrLatency::Bulk
+                } else if packets <= 2 && bytes < 512 {
+                    ItrLatency::Lowest
+                } else {
+                    self
+                }
+            }
+            ItrLatency::Bulk => {
+                if bytes > 25000 {
+                    if packets > 35 {
+                        ItrLatency::Low
+                    } else {
+                        self
+                    }
+                } else if bytes < 6000 {
+                    ItrLatency::Lowest
+                } else {
+                    self
+                }
+            }
+        }
+    }
+}
+
+/// Traffic since the last interrupt and the state of the adaptive ITR
+#[derive(Debug, Clone, Copy)]
+pub(super) struct ItrState {
+    pub(super) rx_packets: usize,
+    pub(super) rx_bytes: usize,
+    pub(super) tx_packets: usize,
+    pub(super) tx_bytes: usize,
+    rx_latency: ItrLatency,
+    tx_latency: ItrLatency,
+    /// Interrupts per second ITR is set to, 0 if off
+    rate: u32,
+}
+
+impl Default for ItrState {
+    fn default() -> Self {
+        ItrState {
+            rx_packets: 0,
+            rx_bytes: 0,
+            tx_packets: 0,
+            tx_bytes: 0,
+            rx_latency: ItrLatency::Low,
+            tx_latency: ItrLatency::Low,
+            rate: 0,
+        }
+    }
+}
+
+/// ITR interval, in units of 256 ns, for `rate` interrupts per second
+fn itr_interval(rate: u32) -> u32 {
+    if rate == 0 {
+        0
+    } else {
+        1_000_000_000 / (rate * 256)
+    }
+}
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Set the interrupt delay timers and throttling
+    pub fn set_coalesce(&mut self, config: CoalesceConfig) -> Result<(), E1000Error> {
+        if let Itr::Fixed(rate) = config.itr {
+            if !(E1000_MIN_ITR_RATE..=E1000_MAX_ITR_RATE).contains(&rate) {
+                error!(
+                    "e1000, ITR of {} interrupts/s isn't in {}..={}",
+                    rate, E1000_MIN_ITR_RATE, E1000_MAX_ITR_RATE
+                );
+                return Err(E1000Error::InvalidConfig);
+            }
+        }
+        self.coalesce = config;
+        self.e1000_coalesce_setup();
+        Ok(())
+    }
+
+    /// The interrupt moderation settings
+    pub fn coalesce(&self) -> CoalesceConfig {
+        self.coalesce
+    }
+
+    /// Interrupts per second ITR currently allows, 0 if not throttled
+    pub fn itr_rate(&self) -> u32 {
+        self.itr.rate
+    }
+
+    /// Program the delay timers and ITR from the settings
+    pub(super) fn e1000_coalesce_setup(&mut self) {
+        self.regs.write(E1000_RDTR, self.coalesce.rx_delay as u32);
+        self.regs.write(E1000_RADV, self.coalesce.rx_abs_delay as u32);
+        self.regs.write(E1000_TIDV, self.coalesce.tx_delay as u32);
+        self.regs.write(E1000_TADV, self.coalesce.tx_abs_delay as u32);
+
+        self.itr = ItrState::default();
+        self.itr.rate = match self.coalesce.itr {
+            Itr::Off => 0,
+            Itr::Fixed(rate) => rate,
+            Itr::Adaptive => ItrLatency::Low.rate(),
+        };
+        self.regs.write(E1000_ITR, itr_interval(self.itr.rate));
+    }
+
+    /// IDE for the tx data descriptors, so TIDV applies to them
+    pub(super) fn e1000_tx_cmd_ide(&self) -> u32 {
+        if self.coalesce.tx_delay != 0 {
+            E1000_TXD_CMD_IDE
+        } else {
+            0
+        }
+    }
+
+    /// Retune ITR from the traffic since the last interrupt, as Linux e1000 does.
+    /// Below 1000 Mb/s the rate stays at 4000 interrupts per second.
+    pub(super) fn e1000_update_itr(&mut self) {
+        let itr = &mut self.itr;
+        itr.rx_latency = itr.rx_latency.update(itr.rx_packets, itr.rx_bytes);
+        itr.tx_latency = itr.tx_latency.update(itr.tx_packets, itr.tx_bytes);
+        itr.rx_packets = 0;
+        itr.rx_bytes = 0;
+        itr.tx_packets = 0;
+        itr.tx_bytes = 0;
+
+        let new_rate = if self.link.up && self.link.speed != LinkSpeed::Mbps1000 {
+            ItrLatency::Bulk.rate()
+        } else {
+            max(itr.rx_latency, itr.tx_latency).rate()
+        };
+        if new_rate == itr.rate {
+            return;
+        }
+        // Rise in steps, to stay biased towards fewer interrupts
+        itr.rate = if new_rate > itr.rate {
+            min(itr.rate + (new_rate >> 2), new_rate)
+        } else {
+            new_rate
+        };
+        let interval = itr_interval(itr.rate);
+        self.regs.write(E1000_ITR, interval);
+    }
+}
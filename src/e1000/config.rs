@@ -0,0 +1,99 @@
+// Ring and buffer sizes and the MAC address of an E1000Device, chosen when it is created
+use super::error::E1000Error;
+use super::filter::is_multicast;
+
+/// Descriptors per ring unless configured otherwise
+pub const E1000_DEFAULT_RING_SIZE: usize = 256;
+/// TDLEN/RDLEN must be a multiple of 128 bytes, i.e. of 8 descriptors [E1000 13.4.29, 13.4.40]
+pub const E1000_RING_SIZE_ALIGN: usize = 128 / 16;
+/// Fewest descriptors per ring
+pub const E1000_MIN_RING_SIZE: usize = E1000_RING_SIZE_ALIGN;
+/// Most descriptors per ring the 8254x families take
+pub const E1000_MAX_RING_SIZE: usize = 4096;
+/// Buffer sizes RCTL.BSIZE and RCTL.BSEX can select
+pub const E1000_MBUF_SIZES: [usize; 7] = [256, 512, 1024, 2048, 4096, 8192, 16384];
+
+/// Builder of the sizes of the rings and the buffers, passed to `E1000Device::new`.
+///
+/// ```ignore
+/// let config = E1000Config::new().tx_ring_size(32).rx_ring_size(32).mbuf_size(1024);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct E1000Config {
+    pub(super) tx_ring_size: usize,
+    pub(super) rx_ring_size: usize,
+    pub(super) mbuf_size: Option<usize>,
+    pub(super) mac_address: Option<[u8; 6]>,
+}
+
+impl Default for E1000Config {
+    fn default() -> Self {
+        E1000Config {
+            tx_ring_size: E1000_DEFAULT_RING_SIZE,
+            rx_ring_size: E1000_DEFAULT_RING_SIZE,
+            mbuf_size: None,
+            mac_address: None,
+        }
+    }
+}
+
+impl E1000Config {
+    /// 256 descriptors per ring, buffers sized by the MTU
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of transmit descriptors
+    pub fn tx_ring_size(mut self, size: usize) -> Self {
+        self.tx_ring_size = size;
+        self
+    }
+
+    /// Number of receive descriptors
+    pub fn rx_ring_size(mut self, size: usize) -> Self {
+        self.rx_ring_size = size;
+        self
+    }
+
+    /// A fixed size of the tx/rx buffers, larger frames spanning several descriptors.
+    /// By default it is 2048 bytes, 4096 for jumbo frames.
+    pub fn mbuf_size(mut self, size: usize) -> Self {
+        self.mbuf_size = Some(size);
+        self
+    }
+
+    /// The MAC address to filter on in RA[0], instead of the permanent one in the EEPROM,
+    /// e.g. a fallback when the EEPROM can't be read
+    pub fn mac_address(mut self, mac: [u8; 6]) -> Self {
+        self.mac_address = Some(mac);
+        self
+    }
+
+    /// Check the sizes against what the hardware supports
+    pub fn validate(&self) -> Result<(), E1000Error> {
+        for (ring, size) in [("tx", self.tx_ring_size), ("rx", self.rx_ring_size)] {
+            if !(E1000_MIN_RING_SIZE..=E1000_MAX_RING_SIZE).contains(&size)
+                || size % E1000_RING_SIZE_ALIGN != 0
+            {
+                error!(
+                    "e1000, {} ring size {} isn't a multiple of {} in {}..={}",
+                    ring, size, E1000_RING_SIZE_ALIGN, E1000_MIN_RING_SIZE, E1000_MAX_RING_SIZE
+                );
+                return Err(E1000Error::InvalidConfig);
+            }
+        }
+        if let Some(size) = self.mbuf_size {
+            if !E1000_MBUF_SIZES.contains(&size) {
+                error!("e1000, no receive buffer size of {} bytes", size);
+                return Err(E1000Error::InvalidConfig);
+            }
+        }
+        if let Some(mac) = self.mac_address {
+            if is_multicast(&mac) {
+                error!("e1000, {:02x?} is not a unicast address", mac);
+                return Err(E1000Error::InvalidConfig);
+            }
+        }
+        Ok(())
+    }
+}
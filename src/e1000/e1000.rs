@@ -1,15 +1,24 @@
 // e1000 Driver for Intel 82540EP/EM
+use super::coalesce::{CoalesceConfig, Itr, ItrState};
+use super::config::E1000Config;
 use super::e1000_const::*;
+use super::eeprom::read_mac_address;
+use super::error::E1000Error;
+use super::filter::{AddressSelect, MulticastOffset, RxMode};
+use super::flowctl::FlowControlConfig;
+use super::interrupt::InterruptCause;
+use super::link::LinkState;
+use super::mtu::{mbuf_size_for, E1000_DEFAULT_MTU};
+use super::rxpool::RxPool;
+use super::stats::HwStats;
+use super::txclean::TxCompletion;
+use super::zerocopy::TxBuffer;
+use super::regs::{E1000Regs, MmioRegs};
 use super::super::Ext;
-use super::super::Volatile;
 use alloc::vec::Vec;
-use core::{cmp::min, mem::size_of, slice::from_raw_parts_mut};
+use core::{cmp::{max, min}, mem::{size_of, size_of_val, take}, slice::from_raw_parts_mut};
 use crate::utils::*;
 
-const TX_RING_SIZE: usize = 256;
-const RX_RING_SIZE: usize = 256;
-const MBUF_SIZE: usize = 2048;
-
 /// Kernel functions that drivers must use
 pub trait KernelFunc {
     /// Page size (usually 4K)
@@ -18,7 +27,8 @@ pub trait KernelFunc {
     // 或请求分配irq
 
     /// Allocate consequent physical memory for DMA;
-    /// Return (cpu virtual address, dma physical address) which is page aligned.
+    /// Return (cpu virtual address, dma physical address) which is page aligned,
+    /// or a virtual address of 0 if the memory can't be allocated.
     //fn dma_alloc_coherent(pages: usize) -> usize;
     fn dma_alloc_coherent(&mut self, pages: usize) -> (usize, usize);
 
@@ -28,17 +38,64 @@ pub trait KernelFunc {
 
 /// Main structure of the e1000 driver.
 /// Used to save members such as ring buffer.
-pub struct E1000Device<'a, K: KernelFunc> {
-    regs: &'static mut [Volatile<u32>],
+pub struct E1000Device<'a, K: KernelFunc, R: E1000Regs = MmioRegs> {
+    pub(super) regs: R,
     rx_ring_dma: usize,
     tx_ring_dma: usize,
-    rx_ring: &'a mut [RxDesc], //可以只为ring buffer加锁
-    tx_ring: &'a mut [TxDesc],
-    rx_mbufs: Vec<usize>,
-    tx_mbufs: Vec<usize>,
-    mbuf_size: usize,
+    pub(super) rx_ring: &'a mut [RxDesc], //可以只为ring buffer加锁
+    pub(super) tx_ring: &'a mut [TxDesc],
+    pub(super) rx_mbufs: Vec<usize>,
+    /// DMA addresses of rx_mbufs
+    pub(super) rx_mbufs_dma: Vec<usize>,
+    /// Pool filling the rx ring instead of rx_mbufs, for zero-copy receive
+    pub(super) rx_pool: Option<&'a RxPool>,
+    /// Index in rx_pool of the buffer of each rx descriptor
+    pub(super) rx_pool_slots: Vec<usize>,
+    /// A packet spanning several rx descriptors, reassembled; kept to be reused
+    pub(super) rx_frame: Vec<u8>,
+    pub(super) tx_mbufs: Vec<usize>,
+    /// DMA addresses of tx_mbufs
+    pub(super) tx_mbufs_dma: Vec<usize>,
+    /// Last context loaded into the hardware by a context descriptor
+    pub(super) tx_context: Option<TxContextDesc>,
+    /// Ring and buffer sizes the device was created with
+    pub(super) config: E1000Config,
+    pub(super) mbuf_size: usize,
+    pub(super) mtu: usize,
+    /// Next tx descriptor to fill, the software tail written to TDT
+    pub(super) tx_next_to_use: usize,
+    /// Oldest tx descriptor not reclaimed yet, the software head
+    pub(super) tx_next_to_clean: usize,
+    /// Length of the packet ending at each tx descriptor, until it is reclaimed
+    pub(super) tx_packet_len: Vec<Option<usize>>,
+    /// Packets and bytes reclaimed since the last `tx_clean`
+    pub(super) tx_completed: TxCompletion,
+    /// Zero-copy buffers of the caller the tx descriptors point at
+    pub(super) tx_loans: Vec<Option<TxBuffer>>,
+    /// Zero-copy buffers the hardware is done with, waiting for `e1000_tx_reclaim`
+    pub(super) tx_done: Vec<TxBuffer>,
+    /// Multicast addresses joined, one entry per `add_multicast`
+    pub(super) mc_addrs: Vec<[u8; 6]>,
+    /// (hash, reference count) of the MTA buckets in use
+    pub(super) mta_refcnt: Vec<(u16, u16)>,
+    pub(super) mc_offset: MulticastOffset,
+    pub(super) rx_mode: RxMode,
+    /// Totals of the statistics counters
+    pub(super) hw_stats: HwStats,
+    /// Link state as of the last link status change
+    pub(super) link: LinkState,
+    /// Link change not yet taken by `link_event`
+    pub(super) link_event: Option<LinkState>,
+    /// Flow control requested by `set_flow_control`
+    pub(super) fc: FlowControlConfig,
+    /// Causes `e1000_irq_enable` enables
+    pub(super) irq_mask: InterruptCause,
+    /// Interrupt moderation set by `set_coalesce`
+    pub(super) coalesce: CoalesceConfig,
+    /// Traffic between interrupts, for the adaptive ITR
+    pub(super) itr: ItrState,
     //phy_interface: PhyInterfaceMode,
-    kfn: K,
+    pub(super) kfn: K,
 }
 
 // struct spinlock e1000_lock;
@@ -48,13 +105,43 @@ pub struct E1000Device<'a, K: KernelFunc> {
 #[derive(Debug, Clone)]
 #[repr(C, align(16))]
 pub struct TxDesc {
-    addr: u64,
-    length: u16,
-    cso: u8,
-    cmd: u8,
-    status: u8,
-    css: u8,
-    special: u16,
+    pub(super) addr: u64,
+    pub(super) length: u16,
+    pub(super) cso: u8,
+    pub(super) cmd: u8,
+    pub(super) status: u8,
+    pub(super) css: u8,
+    pub(super) special: u16,
+}
+
+/// [E1000 3.3.6]
+/// The TCP/IP context descriptor, setting up checksum offload and TSO
+/// for the data descriptors after it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C, align(16))]
+pub struct TxContextDesc {
+    pub(super) ipcss: u8,           /* IP checksum start */
+    pub(super) ipcso: u8,           /* IP checksum offset */
+    pub(super) ipcse: u16,          /* IP checksum end */
+    pub(super) tucss: u8,           /* TCP/UDP checksum start */
+    pub(super) tucso: u8,           /* TCP/UDP checksum offset */
+    pub(super) tucse: u16,          /* TCP/UDP checksum end */
+    pub(super) cmd_and_length: u32, /* PAYLEN, DTYP and TUCMD */
+    pub(super) status: u8,
+    pub(super) hdr_len: u8, /* Header length for TSO */
+    pub(super) mss: u16,    /* Maximum segment size for TSO */
+}
+
+/// [E1000 3.3.7]
+/// The TCP/IP data descriptor, the extended format of TxDesc
+#[derive(Debug, Clone)]
+#[repr(C, align(16))]
+pub struct TxDataDesc {
+    pub(super) addr: u64,
+    pub(super) cmd_type_len: u32, /* DTALEN, DTYP and DCMD */
+    pub(super) status: u8,
+    pub(super) popts: u8, /* Packet options */
+    pub(super) special: u16,
 }
 
 /// [E1000 3.2.3]
@@ -62,28 +149,101 @@ pub struct TxDesc {
 #[derive(Debug, Clone)]
 #[repr(C, align(16))]
 pub struct RxDesc {
-    addr: u64,   /* Address of the descriptor's data buffer */
-    length: u16, /* Length of data DMAed into data buffer */
-    csum: u16,   /* Packet checksum */
-    status: u8,  /* Descriptor status */
-    errors: u8,  /* Descriptor Errors */
-    special: u16,
+    pub(super) addr: u64,   /* Address of the descriptor's data buffer */
+    pub(super) length: u16, /* Length of data DMAed into data buffer */
+    pub(super) csum: u16,   /* Packet checksum */
+    pub(super) status: u8,  /* Descriptor status */
+    pub(super) errors: u8,  /* Descriptor Errors */
+    pub(super) special: u16,
+}
+
+/// What the hardware reported about a received packet
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RxMeta {
+    /// 802.1Q tag control information (PCP, DEI, VLAN ID) stripped by the hardware
+    pub vlan_tag: Option<u16>,
+    /// Whether the IPv4 header checksum is good, None if the hardware didn't check it
+    pub ip_checksum: Option<bool>,
+    /// Whether the TCP/UDP checksum is good, None if the hardware didn't check it
+    pub l4_checksum: Option<bool>,
+    /// Ones' complement sum of the packet from the Ethernet payload on
+    pub csum: u16,
+}
+
+impl RxMeta {
+    pub(super) fn from_desc(desc: &RxDesc) -> Self {
+        let status = desc.status as u32;
+        let errors = desc.errors as u32;
+        let checked = |calculated, error| {
+            if status & (E1000_RXD_STAT_IXSM | calculated) == calculated {
+                Some(errors & error == 0)
+            } else {
+                None
+            }
+        };
+        RxMeta {
+            vlan_tag: if status & E1000_RXD_STAT_VP != 0 {
+                Some(desc.special)
+            } else {
+                None
+            },
+            ip_checksum: checked(E1000_RXD_STAT_IPCS, E1000_RXD_ERR_IPE),
+            l4_checksum: checked(E1000_RXD_STAT_TCPCS, E1000_RXD_ERR_TCPE),
+            csum: desc.csum,
+        }
+    }
+
+    /// The hardware verified the TCP/UDP checksum (and the IPv4 one, if any),
+    /// so the stack can skip it (`CHECKSUM_UNNECESSARY`)
+    pub fn checksum_verified(&self) -> bool {
+        self.l4_checksum == Some(true) && self.ip_checksum != Some(false)
+    }
+}
+
+/// A received packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RxPacket {
+    pub data: Vec<u8>,
+    pub meta: RxMeta,
 }
 
 impl<'a, K: KernelFunc> E1000Device<'a, K> {
     /// New an e1000 device by Allocating memory
-    pub fn new(mut kfn: K, mapped_regs: usize) -> Result<Self, i32> {
+    /// mapped_regs is the memory address at which the e1000's registers are mapped.
+    pub fn new(kfn: K, mapped_regs: usize, config: E1000Config) -> Result<Self, E1000Error> {
         info!("New E1000 device @ {:#x}", mapped_regs);
+        Self::with_regs(kfn, MmioRegs::new(mapped_regs), config)
+    }
+}
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// New an e1000 device on top of any register access backend.
+    /// Whatever was allocated is freed again if a DMA allocation fails.
+    pub fn with_regs(mut kfn: K, regs: R, config: E1000Config) -> Result<Self, E1000Error> {
+        config.validate()?;
+        let tx_ring_size = config.tx_ring_size;
+        let rx_ring_size = config.rx_ring_size;
+        let mbuf_size = config.mbuf_size.unwrap_or_else(|| mbuf_size_for(E1000_DEFAULT_MTU));
+
         // 分配的ring内存空间需要16字节对齐
         let alloc_tx_ring_pages =
-            ((TX_RING_SIZE * size_of::<TxDesc>()) + (K::PAGE_SIZE - 1)) / K::PAGE_SIZE;
+            ((tx_ring_size * size_of::<TxDesc>()) + (K::PAGE_SIZE - 1)) / K::PAGE_SIZE;
         let alloc_rx_ring_pages =
-            ((RX_RING_SIZE * size_of::<RxDesc>()) + (K::PAGE_SIZE - 1)) / K::PAGE_SIZE;
+            ((rx_ring_size * size_of::<RxDesc>()) + (K::PAGE_SIZE - 1)) / K::PAGE_SIZE;
         let (tx_ring_vaddr, tx_ring_dma) = kfn.dma_alloc_coherent(alloc_tx_ring_pages);
+        if tx_ring_vaddr == 0 {
+            error!("e1000, alloc dma tx ring failed");
+            return Err(E1000Error::AllocFailed);
+        }
         let (rx_ring_vaddr, rx_ring_dma) = kfn.dma_alloc_coherent(alloc_rx_ring_pages);
+        if rx_ring_vaddr == 0 {
+            error!("e1000, alloc dma rx ring failed");
+            kfn.dma_free_coherent(tx_ring_vaddr, alloc_tx_ring_pages);
+            return Err(E1000Error::AllocFailed);
+        }
 
-        let tx_ring = unsafe { from_raw_parts_mut(tx_ring_vaddr as *mut TxDesc, TX_RING_SIZE) };
-        let rx_ring = unsafe { from_raw_parts_mut(rx_ring_vaddr as *mut RxDesc, RX_RING_SIZE) };
+        let tx_ring = unsafe { from_raw_parts_mut(tx_ring_vaddr as *mut TxDesc, tx_ring_size) };
+        let rx_ring = unsafe { from_raw_parts_mut(rx_ring_vaddr as *mut RxDesc, rx_ring_size) };
 
         tx_ring.fill(TxDesc {
             addr: 0,
@@ -103,38 +263,26 @@ impl<'a, K: KernelFunc> E1000Device<'a, K> {
             special: 0,
         });
 
-        let mut tx_mbufs = Vec::with_capacity(tx_ring.len());
-        let mut rx_mbufs = Vec::with_capacity(rx_ring.len());
-
-        // 一起申请所有TX内存
-        let alloc_tx_buffer_pages =
-            ((TX_RING_SIZE * MBUF_SIZE) + (K::PAGE_SIZE - 1)) / K::PAGE_SIZE;
-        let (mut tx_mbufs_vaddr, mut tx_mbufs_dma) = kfn.dma_alloc_coherent(alloc_tx_buffer_pages);
-
-        for i in 0..TX_RING_SIZE {
-            tx_ring[i].status = E1000_TXD_STAT_DD as u8;
-            tx_ring[i].addr = tx_mbufs_dma as u64;
-            tx_mbufs.push(tx_mbufs_vaddr);
-            tx_mbufs_dma += MBUF_SIZE;
-            tx_mbufs_vaddr += MBUF_SIZE;
-        }
-
-        // 一起申请所有RX内存
-        let alloc_rx_buffer_pages =
-            ((RX_RING_SIZE * MBUF_SIZE) + (K::PAGE_SIZE - 1)) / K::PAGE_SIZE;
-        //let mut rx_mbufs_dma: usize = K::dma_alloc_coherent(alloc_rx_buffer_pages);
-        let (mut rx_mbufs_vaddr, mut rx_mbufs_dma) = kfn.dma_alloc_coherent(alloc_rx_buffer_pages);
-        if rx_mbufs_vaddr == 0 {
-            panic!("e1000, alloc dma rx buffer failed");
-        }
-
-        for i in 0..RX_RING_SIZE {
-            rx_ring[i].addr = rx_mbufs_dma as u64;
-            rx_mbufs.push(rx_mbufs_vaddr);
-            rx_mbufs_dma += MBUF_SIZE;
-            rx_mbufs_vaddr += MBUF_SIZE;
-        }
-        fence_w();
+        // 一起申请所有TX/RX内存
+        let tx_mbufs = match Self::alloc_mbufs(&mut kfn, tx_ring_size, mbuf_size) {
+            Some(mbufs) => mbufs,
+            None => {
+                error!("e1000, alloc dma tx buffer failed");
+                kfn.dma_free_coherent(rx_ring_vaddr, alloc_rx_ring_pages);
+                kfn.dma_free_coherent(tx_ring_vaddr, alloc_tx_ring_pages);
+                return Err(E1000Error::AllocFailed);
+            }
+        };
+        let rx_mbufs = match Self::alloc_mbufs(&mut kfn, rx_ring_size, mbuf_size) {
+            Some(mbufs) => mbufs,
+            None => {
+                error!("e1000, alloc dma rx buffer failed");
+                Self::free_mbufs(&mut kfn, &tx_mbufs.0, mbuf_size);
+                kfn.dma_free_coherent(rx_ring_vaddr, alloc_rx_ring_pages);
+                kfn.dma_free_coherent(tx_ring_vaddr, alloc_tx_ring_pages);
+                return Err(E1000Error::AllocFailed);
+            }
+        };
 
         // Slice切片，内存連續的動態大小的序列；
         // array, 数组
@@ -157,149 +305,392 @@ impl<'a, K: KernelFunc> E1000Device<'a, K> {
         只能用于只有单个非零大小字段（可能还有其他零大小字段，如PhantomData<T>）的
         struct或enum 中。使得整个结构的内存布局和ABI被保证与该非零字段相同。
         */
-        // 0x00000 ~ 0x1FFFF, I/O-Mapped Internal Registers and Memories
-        let len = 0x1FFFF / size_of::<u32>();
-        // 处理网卡寄存器配置: 由一个指针和一个长度len形成一个slice切片。len是元素的个数，而非字节数。
-        let regs = unsafe { from_raw_parts_mut(mapped_regs as *mut Volatile<u32>, len) };
-
         let mut e1000dev = E1000Device {
             regs,
             rx_ring_dma,
             tx_ring_dma,
             rx_ring,
             tx_ring,
-            rx_mbufs,
-            tx_mbufs,
-            mbuf_size: MBUF_SIZE,
+            rx_mbufs: Vec::new(),
+            rx_mbufs_dma: Vec::new(),
+            rx_pool: None,
+            rx_pool_slots: Vec::new(),
+            rx_frame: Vec::new(),
+            tx_mbufs: Vec::new(),
+            tx_mbufs_dma: Vec::new(),
+            tx_context: None,
+            config,
+            mbuf_size,
+            mtu: E1000_DEFAULT_MTU,
+            tx_next_to_use: 0,
+            tx_next_to_clean: 0,
+            tx_packet_len: Vec::with_capacity(tx_ring_size),
+            tx_completed: TxCompletion::default(),
+            tx_loans: Vec::with_capacity(tx_ring_size),
+            tx_done: Vec::new(),
+            mc_addrs: Vec::new(),
+            mta_refcnt: Vec::new(),
+            mc_offset: MulticastOffset::Bits47_36,
+            rx_mode: RxMode::Normal,
+            hw_stats: HwStats::default(),
+            link: LinkState::default(),
+            link_event: None,
+            fc: FlowControlConfig::default(),
+            irq_mask: InterruptCause::DEFAULT,
+            coalesce: CoalesceConfig::default(),
+            itr: ItrState::default(),
             kfn,
         };
-        e1000dev.e1000_init();
+        for _ in 0..tx_ring_size {
+            e1000dev.tx_packet_len.push(None);
+            e1000dev.tx_loans.push(None);
+        }
+        e1000dev.e1000_use_mbufs(tx_mbufs, rx_mbufs, mbuf_size);
+        e1000dev.e1000_init()?;
 
         Ok(e1000dev)
     }
 
-    /// Initialize e1000 driver  
-    /// mapped_regs is the memory address at which the e1000's registers are mapped.
-    pub fn e1000_init(&mut self) {
-        let stat = self.regs[E1000_STAT].read();
-        let ctl = self.regs[E1000_CTL].read();
+    /// Allocate a DMA buffer of `size` bytes for each of `count` descriptors, all together.
+    /// Returns the virtual and the DMA addresses of the buffers.
+    pub(super) fn alloc_mbufs(
+        kfn: &mut K,
+        count: usize,
+        size: usize,
+    ) -> Option<(Vec<usize>, Vec<usize>)> {
+        let pages = ((count * size) + (K::PAGE_SIZE - 1)) / K::PAGE_SIZE;
+        let (mut vaddr, mut dma) = kfn.dma_alloc_coherent(pages);
+        if vaddr == 0 {
+            return None;
+        }
+
+        let mut vaddrs = Vec::with_capacity(count);
+        let mut dmas = Vec::with_capacity(count);
+        for _ in 0..count {
+            vaddrs.push(vaddr);
+            dmas.push(dma);
+            vaddr += size;
+            dma += size;
+        }
+        Some((vaddrs, dmas))
+    }
+
+    /// Free buffers allocated by `alloc_mbufs`
+    pub(super) fn free_mbufs(kfn: &mut K, mbufs: &[usize], size: usize) {
+        if let Some(&vaddr) = mbufs.first() {
+            let pages = ((mbufs.len() * size) + (K::PAGE_SIZE - 1)) / K::PAGE_SIZE;
+            kfn.dma_free_coherent(vaddr, pages);
+        }
+    }
+
+    /// Hand freshly allocated (virtual, DMA) buffers to the rings, every descriptor
+    /// goes back to its initial state
+    pub(super) fn e1000_use_mbufs(
+        &mut self,
+        tx_mbufs: (Vec<usize>, Vec<usize>),
+        rx_mbufs: (Vec<usize>, Vec<usize>),
+        size: usize,
+    ) {
+        for index in 0..self.tx_ring.len() {
+            self.e1000_tx_return(index);
+        }
+        for (desc, &dma) in self.tx_ring.iter_mut().zip(tx_mbufs.1.iter()) {
+            desc.addr = dma as u64;
+            desc.length = 0;
+            desc.cmd = 0;
+            desc.status = E1000_TXD_STAT_DD as u8;
+        }
+        for (desc, &dma) in self.rx_ring.iter_mut().zip(rx_mbufs.1.iter()) {
+            desc.addr = dma as u64;
+            desc.status = 0;
+        }
+        fence_w();
+
+        self.tx_mbufs = tx_mbufs.0;
+        self.tx_mbufs_dma = tx_mbufs.1;
+        self.rx_mbufs = rx_mbufs.0;
+        self.rx_mbufs_dma = rx_mbufs.1;
+        self.mbuf_size = size;
+    }
+
+    /// Initialize e1000 driver.
+    /// Fails if the device doesn't come out of reset.
+    pub fn e1000_init(&mut self) -> Result<(), E1000Error> {
+        let stat = self.regs.read(E1000_STAT);
+        let ctl = self.regs.read(E1000_CTL);
         info!("e1000 CTL: {:#x}, Status: {:#x}", ctl, stat);
 
         // Reset the device
-        self.regs[E1000_IMS].write(0); // disable interrupts
-        self.regs[E1000_CTL].write(ctl | E1000_CTL_RST);
-        self.regs[E1000_IMS].write(0); // redisable interrupts
+        self.regs.write(E1000_IMS, 0); // disable interrupts
+        self.e1000_reset()?;
+        self.regs.write(E1000_IMS, 0); // redisable interrupts
 
         // 内存壁垒 fence
         //__sync_synchronize();
         fence_w();
 
         // [E1000 14.5] Transmit initialization
-        if (self.tx_ring.len() * size_of::<TxDesc>()) % 128 != 0 {
+        if size_of_val(self.tx_ring) % 128 != 0 {
             //panic("e1000");
             error!("e1000, size of tx_ring is invalid");
         }
 
         // transmitter control bits.
-        self.regs[E1000_TCTL].write(
+        self.regs.write(
+            E1000_TCTL,
             E1000_TCTL_EN |  // enable
             E1000_TCTL_PSP |  // pad short packets
             (0x10 << E1000_TCTL_CT_SHIFT) |  // collision stuff
             (0x40 << E1000_TCTL_COLD_SHIFT),
         );
-        self.regs[E1000_TIPG].write(10 | (8 << 10) | (6 << 20)); // inter-pkt gap
+        self.regs.write(E1000_TIPG, 10 | (8 << 10) | (6 << 20)); // inter-pkt gap
 
-        self.regs[E1000_TDBAL].write(self.tx_ring_dma as u32);
-        self.regs[E1000_TDBAH].write((self.tx_ring_dma >> 32) as u32);
-        self.regs[E1000_TDLEN].write((self.tx_ring.len() * size_of::<TxDesc>()) as u32);
+        self.regs.write(E1000_TDBAL, self.tx_ring_dma as u32);
+        self.regs.write(E1000_TDBAH, (self.tx_ring_dma >> 32) as u32);
+        self.regs.write(E1000_TDLEN, size_of_val(self.tx_ring) as u32);
 
-        self.regs[E1000_TDT].write(0); // TX Desc Tail
-        self.regs[E1000_TDH].write(0); // TX Desc Head
+        self.e1000_tx_ring_reset(); // nor will it read lent buffers any more
+        self.tx_context = None; // the reset dropped the offload context
 
         // [E1000 14.4] Receive initialization
         info!("rx ring 0: {:x?}",self.rx_ring[0]);
-        if (self.rx_ring.len() * size_of::<RxDesc>()) % 128 != 0 {
+        if size_of_val(self.rx_ring) % 128 != 0 {
             error!("e1000, size of rx_ring is invalid");
         }
 
         // receiver control bits.
-        self.regs[E1000_RCTL].write((
-            E1000_RCTL_EN |  // enable receiver
+        self.regs.write(
+            E1000_RCTL,
+            (E1000_RCTL_EN |  // enable receiver
             E1000_RCTL_BAM |  // enable broadcast
-            E1000_RCTL_SZ_2048 |  // 2048-byte rx buffers
-            E1000_RCTL_SECRC  // strip CRC
+            self.rctl_buffer_bits() |  // rx buffer size, long packets for jumbo frames
+            E1000_RCTL_SECRC |  // strip CRC
+            ((self.mc_offset as u32) << E1000_RCTL_MO_SHIFT)  // multicast hash offset
             ) & !(0b11 << 10) // Just for e1000e DTYP bits[11:10]=00 : Legacy description type
-        ); 
-        self.regs[E1000_RFCTL].write(0); //e1000e RFCTL.EXSTEN bits[15]=0 : Legacy Desc
-        info!("e1000 RCTL: {:#x}, RFCTL: {:#x}", self.regs[E1000_RCTL].read(), self.regs[E1000_RFCTL].read());
+        );
+        self.set_rx_mode(self.rx_mode);
+        self.e1000_fc_setup();
+        self.regs.write(E1000_RFCTL, 0); //e1000e RFCTL.EXSTEN bits[15]=0 : Legacy Desc
+        info!("e1000 RCTL: {:#x}, RFCTL: {:#x}", self.regs.read(E1000_RCTL), self.regs.read(E1000_RFCTL));
+
+        self.regs.write(E1000_RDBAL, self.rx_ring_dma as u32);
+        self.regs.write(E1000_RDBAH, (self.rx_ring_dma >> 32) as u32);
+        self.regs.write(E1000_RDLEN, size_of_val(self.rx_ring) as u32);
+
+        self.regs.write(E1000_RDH, 0);
+        self.regs.write(E1000_RDT, (self.rx_ring.len() - 1) as u32);
+
+        // filter by the permanent MAC address, e.g. qemu's 52:54:00:12:34:56
+        // or by the one chosen through E1000Config
+        let mac = match self.config.mac_address {
+            Some(mac) => Ok(mac),
+            None => self.mac_address(),
+        };
+        match mac {
+            Ok(mac) => {
+                info!("e1000 MAC address: {:02x?}", mac);
+                self.e1000_rar_set(0, mac, AddressSelect::Destination);
+            }
+            Err(_) => warn!("e1000, no valid MAC address in the EEPROM, leave RA[0] as it is"),
+        }
+        // No extra unicast filters
+        for i in 1..E1000_RAR_ENTRIES {
+            self.e1000_rar_clear(i);
+        }
 
-        self.regs[E1000_RDBAL].write(self.rx_ring_dma as u32);
-        self.regs[E1000_RDBAH].write((self.rx_ring_dma >> 32) as u32);
-        self.regs[E1000_RDLEN].write((self.rx_ring.len() * size_of::<RxDesc>()) as u32);
+        // multicast table
+        self.e1000_mta_sync();
 
-        self.regs[E1000_RDH].write(0);
-        self.regs[E1000_RDT].write((RX_RING_SIZE - 1) as u32);
+        // IPv4 and TCP/UDP checksum offload, the packet checksum starting after the Ethernet header
+        self.regs.write(E1000_RXCSUM, E1000_RXCSUM_IPOFLD | E1000_RXCSUM_TUOFLD | ETH_HLEN);
 
-        // filter by qemu's MAC address, 52:54:00:12:34:56
-        //self.regs[E1000_RA].write(0x6c005452);
-        //self.regs[E1000_RA + 1].write(0x88f8 | (1 << 31)); //52:54:00:6c:f8:88
+        // 802.1Q VLAN: standard ether type, empty filter table
+        self.regs.write(E1000_VET, ETH_P_8021Q as u32);
+        self.vlan_filter_clear();
 
-        // multicast table
-        for i in 0..(4096 / 32) {
-            self.regs[E1000_MTA + i].write(0);
-        }
+        // interrupt delay timers and throttling, by default an interrupt after every packet
+        self.e1000_coalesce_setup();
+
+        //self.regs.write(E1000_ICS, 1 << 7); //手动测试触发对应中断
 
-        self.regs[E1000_TIDV].write(0);
-        self.regs[E1000_TADV].write(0);
-        // ask e1000 for receive interrupts.
-        self.regs[E1000_RDTR].write(0); // interrupt after every received packet (no timer)
-        self.regs[E1000_RADV].write(0); // interrupt after every packet (no timer)
+        // the causes chosen through the interrupt mask, RXT0 | LSC by default
+        self.regs.write(E1000_IMS, self.irq_mask.bits());
 
-        self.regs[E1000_ITR].write(0); //Interrupt Throttle interval has expired, and an interrupt will be generated
+        self.regs.read(E1000_ICR); // clear ints
+        self.e1000_write_flush();
 
-        //self.regs[E1000_ICS].write(1 << 7); //手动测试触发对应中断
+        // link changes are reported against the state found here
+        self.link = self.link_state();
+        self.link_event = None;
+        info!("e1000_init has been completed");
+        Ok(())
+    }
 
-        self.regs[E1000_IMS].write(1 << 7); // RXT0 - Receiver Timer Interrupt , RXDW -- Receiver Descriptor Write Back
+    /// Reset the device, waiting for CTRL.RST to clear itself once the reset is done
+    fn e1000_reset(&mut self) -> Result<(), E1000Error> {
+        let ctl = self.regs.read(E1000_CTL);
+        self.regs.write(E1000_CTL, ctl | E1000_CTL_RST);
+        if !(0..E1000_RESET_TIMEOUT).any(|_| self.regs.read(E1000_CTL) & E1000_CTL_RST == 0) {
+            error!("e1000, the device didn't come out of reset");
+            return Err(E1000Error::ResetTimeout);
+        }
+        Ok(())
+    }
 
-        self.regs[E1000_ICR].read(); // clear ints
+    /// Stop the device before its memory goes away [E1000 14.9]:
+    /// no more interrupts or received packets, the packets queued for transmission
+    /// sent, then a reset so that nothing is DMAed any more.
+    /// Fails if the device doesn't come out of reset, so it may still be running.
+    pub(super) fn e1000_quiesce(&mut self) -> Result<(), E1000Error> {
+        self.e1000_irq_disable();
+        let rctl = self.regs.read(E1000_RCTL);
+        self.regs.write(E1000_RCTL, rctl & !E1000_RCTL_EN);
         self.e1000_write_flush();
-        info!("e1000_init has been completed");
+
+        // Let the transmitter catch up with TDT
+        let drained = (0..E1000_TX_DRAIN_TIMEOUT)
+            .any(|_| self.regs.read(E1000_TDH) == self.regs.read(E1000_TDT));
+        if !drained {
+            warn!("e1000, tx ring not drained, drop the packets in flight");
+        }
+        let tctl = self.regs.read(E1000_TCTL);
+        self.regs.write(E1000_TCTL, tctl & !E1000_TCTL_EN);
+        self.e1000_write_flush();
+
+        let reset = self.e1000_reset();
+        self.e1000_irq_disable();
+        fence();
+        reset
     }
 
-    /// Transmitting network packets
-    pub fn e1000_transmit(&mut self, packet: &[u8]) -> i32 {
-        let tindex = self.regs[E1000_TDT].read() as usize;
-        info!("Read E1000_TDT = {:#x}", tindex);
-        //info!("TX Desc = {:#x?}", self.tx_ring[tindex]);
-        if (self.tx_ring[tindex].status & E1000_TXD_STAT_DD as u8) == 0 {
+    /// Quiesce and drop the device like dropping it does, giving back the zero-copy
+    /// tx buffers not reclaimed yet: the hardware no longer reads them.
+    pub fn shutdown(mut self) -> Vec<TxBuffer> {
+        if self.e1000_quiesce().is_err() {
+            warn!("e1000, tx buffers given back from a device that may still be running");
+        }
+        self.e1000_tx_ring_reset();
+        take(&mut self.tx_done)
+    }
+
+    /// The permanent MAC address burned into the EEPROM.
+    /// Fails if the EEPROM can't be read or its checksum is invalid.
+    pub fn mac_address(&mut self) -> Result<[u8; 6], E1000Error> {
+        read_mac_address(&mut self.regs)
+    }
+
+    /// Transmitting network packets, returns the number of bytes queued
+    pub fn e1000_transmit(&mut self, packet: &[u8]) -> Result<usize, E1000Error> {
+        self.e1000_xmit(&[packet], 0, 0)
+    }
+
+    /// Transmitting a network packet gathered from several fragments, e.g. the headers
+    /// and the payload, each fragment going to descriptors of its own
+    pub fn e1000_transmit_sg(&mut self, frags: &[&[u8]]) -> Result<usize, E1000Error> {
+        self.e1000_xmit(frags, 0, 0)
+    }
+
+    /// Put a packet into the descriptors from TDT on, one per mbuf each fragment takes,
+    /// EOP on the last one.
+    /// `cmd` are the command bits besides RS and EOP, `special` goes to the descriptors as it is.
+    pub(super) fn e1000_xmit(
+        &mut self,
+        frags: &[&[u8]],
+        cmd: u32,
+        special: u16,
+    ) -> Result<usize, E1000Error> {
+        let mbuf_size = self.mbuf_size;
+        let total: usize = frags.iter().map(|frag| frag.len()).sum();
+        if total > self.max_frame_size() {
+            error!("The packet: {} to be send is TOO LARGE", total);
+            return Err(E1000Error::FrameTooLarge);
+        }
+        let descs: usize = frags.iter().map(|frag| (frag.len() + mbuf_size - 1) / mbuf_size).sum();
+        let count = max(1, descs);
+        let ring_len = self.tx_ring.len();
+        if count >= ring_len {
+            error!("e1000, {} fragments need more than {} descriptors", frags.len(), ring_len);
+            return Err(E1000Error::FrameTooLarge);
+        }
+        let mut tindex = self.e1000_tx_reserve(count)?;
+
+        info!(">>>>>>>>> TX PKT {}", total);
+        info!("\n\r");
+        //print_hex_dump(tx_mbuf, 64);
+
+        let ide = self.e1000_tx_cmd_ide();
+        let mut chunks = frags.iter().flat_map(|frag| frag.chunks(mbuf_size));
+        for i in 0..count {
+            let chunk = chunks.next().unwrap_or(&[]);
+            let length = self.e1000_tx_copy(tindex, chunk);
+            let eop = if i + 1 == count { E1000_TXD_CMD_EOP } else { 0 };
+
+            let desc = &mut self.tx_ring[tindex];
+            desc.addr = self.tx_mbufs_dma[tindex] as u64;
+            desc.length = length as u16;
+            desc.cso = 0;
+            desc.css = 0;
+            desc.status = 0;
+            desc.cmd = (E1000_TXD_CMD_RS | eop | ide | cmd) as u8;
+            desc.special = special;
+            tindex = (tindex + 1) % ring_len;
+        }
+
+        self.e1000_tx_kick(tindex, total);
+
+        Ok(total)
+    }
+
+    /// The index of the software tail, if `count` descriptors from it are free.
+    /// Completed descriptors are reclaimed first when there's not enough room.
+    pub(super) fn e1000_tx_reserve(&mut self, count: usize) -> Result<usize, E1000Error> {
+        if self.tx_free_slots() < count {
+            self.e1000_tx_reap();
+        }
+        if self.tx_free_slots() < count {
             error!("E1000 hasn't finished the corresponding previous transmission request");
-            return -1;
+            return Err(E1000Error::RingFull);
         }
+        info!("TX tail = {:#x}", self.tx_next_to_use);
+        Ok(self.tx_next_to_use)
+    }
 
+    /// Copy a packet into the mbuf of descriptor `index`, returns the length copied
+    pub(super) fn e1000_tx_copy(&mut self, index: usize, packet: &[u8]) -> usize {
         let mut length = packet.len();
         if length > self.mbuf_size {
             error!("The packet: {} to be send is TOO LARGE", length);
             length = min(length, self.mbuf_size);
         }
 
-        let mbuf = unsafe { from_raw_parts_mut(self.tx_mbufs[tindex] as *mut u8, length) };
-        mbuf.copy_from_slice(packet);
-
-        info!(">>>>>>>>> TX PKT {}", length);
-        info!("\n\r");
-        //print_hex_dump(tx_mbuf, 64);
+        let mbuf = unsafe { from_raw_parts_mut(self.tx_mbufs[index] as *mut u8, length) };
+        mbuf.copy_from_slice(&packet[..length]);
+        length
+    }
 
-        self.tx_ring[tindex].length = length as u16;
-        self.tx_ring[tindex].status = 0;
-        self.tx_ring[tindex].cmd = (E1000_TXD_CMD_RS | E1000_TXD_CMD_EOP) as u8;
+    /// Hand the descriptors before `tail` to the hardware, the last one ending a packet of `len` bytes
+    pub(super) fn e1000_tx_kick(&mut self, tail: usize, len: usize) {
+        let ring_len = self.tx_ring.len();
+        self.tx_packet_len[(tail + ring_len - 1) % ring_len] = Some(len);
+        self.tx_next_to_use = tail;
 
-        self.regs[E1000_TDT].write(((tindex + 1) % TX_RING_SIZE) as u32);
+        // descriptors must be in memory before the hardware fetches them
+        fence_w();
+        self.regs.write(E1000_TDT, tail as u32);
 
         self.e1000_write_flush();
         // sync
         fence_w();
+    }
 
-        length as i32
+    /// The tx descriptor `index` in the TCP/IP context format
+    pub(super) fn tx_context_desc(&mut self, index: usize) -> &mut TxContextDesc {
+        unsafe { &mut *(&mut self.tx_ring[index] as *mut TxDesc as *mut TxContextDesc) }
+    }
+
+    /// The tx descriptor `index` in the TCP/IP data format
+    pub(super) fn tx_data_desc(&mut self, index: usize) -> &mut TxDataDesc {
+        unsafe { &mut *(&mut self.tx_ring[index] as *mut TxDesc as *mut TxDataDesc) }
     }
 
     // Todo: send and recv lock
@@ -309,22 +700,119 @@ impl<'a, K: KernelFunc> E1000Device<'a, K> {
         // Create and deliver an mbuf for each packet (using net_rx()).
         //let mut recv_packets = VecDeque::new();
         let mut recv_packets = Vec::new();
-        let mut rindex = (self.regs[E1000_RDT].read() as usize + 1) % RX_RING_SIZE;
+        while let Some(packet) = self.e1000_rx_next() {
+            recv_packets.push(packet.data);
+        }
+        info!("e1000_recv\n\r");
+
+        if !recv_packets.is_empty() {
+            Some(recv_packets)
+        } else {
+            None
+        }
+    }
+
+    /// Receiving network packets, together with what the hardware reported about them
+    pub fn e1000_recv_packets(&mut self) -> Option<Vec<RxPacket>> {
+        let mut recv_packets = Vec::new();
+        while let Some(packet) = self.e1000_rx_next() {
+            recv_packets.push(packet);
+        }
+
+        if !recv_packets.is_empty() {
+            Some(recv_packets)
+        } else {
+            None
+        }
+    }
 
-        //info!("RX Desc {} = {:#x?}", rindex, self.rx_ring[rindex]);
-        if self.rx_ring[rindex].addr == 0 {
+    /// The first and the last descriptor of the packet at RDT + 1, if the hardware is done with it.
+    /// A packet larger than an mbuf spans several descriptors up to the one with EOP.
+    pub(super) fn e1000_rx_peek(&self) -> Option<(usize, usize)> {
+        let ring_len = self.rx_ring.len();
+        let first = (self.regs.read(E1000_RDT) as usize + 1) % ring_len;
+
+        //info!("RX Desc {} = {:#x?}", first, self.rx_ring[first]);
+        if self.rx_ring[first].addr == 0 {
             error!("E1000 RX Desc.addr is invalid");
             return None;
         }
 
-        // DD设为1时，内存中的接收包是完整的
-        while (self.rx_ring[rindex].status & E1000_RXD_STAT_DD as u8) != 0 {
-            info!("Read E1000_RDT + 1 = {:#x}", rindex);
+        // DD设为1时，内存中的接收包是完整的, 直到EOP
+        let mut last = first;
+        loop {
+            let status = self.rx_ring[last].status as u32;
+            if status & E1000_RXD_STAT_DD == 0 {
+                return None;
+            }
+            if status & E1000_RXD_STAT_EOP != 0 {
+                return Some((first, last));
+            }
+            last = (last + 1) % ring_len;
+            if last == first {
+                error!("e1000, no end of packet in the whole rx ring");
+                return None;
+            }
+        }
+    }
+
+    /// Take the packet at RDT + 1 off the ring, if the hardware is done with it.
+    pub(super) fn e1000_rx_next(&mut self) -> Option<RxPacket> {
+        let mut packet = None;
+        self.e1000_rx_take(|data, meta| {
+            packet = Some(RxPacket {
+                data: data.to_vec(),
+                meta,
+            })
+        });
+        packet
+    }
+
+    /// Take the packet at RDT + 1 off the ring, if the hardware is done with it,
+    /// and hand it to `on_frame`: straight from its mbuf, or reassembled in `rx_frame`
+    /// when it spans several. Returns whether there was a packet.
+    pub(super) fn e1000_rx_take<F>(&mut self, on_frame: F) -> bool
+    where
+        F: FnOnce(&[u8], RxMeta),
+    {
+        let ring_len = self.rx_ring.len();
+        let (first, last) = match self.e1000_rx_peek() {
+            Some(descs) => descs,
+            None => return false,
+        };
+
+        info!("Read E1000_RDT + 1 = {:#x}", first);
+        let meta = RxMeta::from_desc(&self.rx_ring[last]);
+        let len = if first == last {
+            let len = self.rx_ring[first].length as usize;
+            let mbuf = unsafe { from_raw_parts_mut(self.rx_buf_vaddr(first) as *mut u8, len) };
+            on_frame(mbuf, meta);
+            len
+        } else {
+            let mut frame = take(&mut self.rx_frame);
+            frame.clear();
+            let mut rindex = first;
+            loop {
+                let len = self.rx_ring[rindex].length as usize;
+                let mbuf = unsafe { from_raw_parts_mut(self.rx_buf_vaddr(rindex) as *mut u8, len) };
+                frame.extend_from_slice(mbuf);
+                if rindex == last {
+                    break;
+                }
+                rindex = (rindex + 1) % ring_len;
+            }
+            on_frame(&frame, meta);
+            let len = frame.len();
+            // kept for the next packet spanning several mbufs
+            self.rx_frame = frame;
+            len
+        };
+
+        let mut rindex = first;
+        loop {
             let len = self.rx_ring[rindex].length as usize;
-            let mbuf = unsafe { from_raw_parts_mut(self.rx_mbufs[rindex] as *mut u8, len) };
+            let mbuf = unsafe { from_raw_parts_mut(self.rx_buf_vaddr(rindex) as *mut u8, len) };
             info!("RX PKT {} <<<<<<<<<", len);
-            //recv_packets.push_back(mbuf.to_vec());
-            recv_packets.push(mbuf.to_vec());
 
             // Deliver the mbuf to the network stack
             net_rx(mbuf);
@@ -334,21 +822,20 @@ impl<'a, K: KernelFunc> E1000Device<'a, K> {
             mbuf[..min(64, len)].fill(0);
 
             self.rx_ring[rindex].status = 0;
-            self.regs[E1000_RDT].write(rindex as u32);
-
-            self.e1000_write_flush();
-            // sync
-            fence_w();
-
-            rindex = (rindex + 1) % RX_RING_SIZE;
+            if rindex == last {
+                break;
+            }
+            rindex = (rindex + 1) % ring_len;
         }
-        info!("e1000_recv\n\r");
+        self.regs.write(E1000_RDT, last as u32);
 
-        if recv_packets.len() > 0 {
-            Some(recv_packets)
-        } else {
-            None
-        }
+        self.e1000_write_flush();
+        // sync
+        fence_w();
+
+        self.itr.rx_packets += 1;
+        self.itr.rx_bytes += len;
+        true
     }
     
     // 参考
@@ -358,36 +845,78 @@ impl<'a, K: KernelFunc> E1000Device<'a, K> {
 
     /// Clear Interrupt
     pub fn e1000_irq_disable(&mut self) {
-        self.regs[E1000_IMC].write(!0); // 只有在对应位写1才能清中断Mask，以屏蔽对应中断
+        self.regs.write(E1000_IMC, !0); // 只有在对应位写1才能清中断Mask，以屏蔽对应中断
         self.e1000_write_flush();
     }
 
-    /// Enable Interrupts
+    /// Enable Interrupts: the causes chosen by `enable_interrupts` / `disable_interrupts`
     pub fn e1000_irq_enable(&mut self) {
-        self.regs[E1000_IMS].write(IMS_ENABLE_MASK);
+        self.regs.write(E1000_IMS, self.irq_mask.bits());
         self.e1000_write_flush();
     }
 
     /// flush e1000 status
     pub fn e1000_write_flush(&mut self) {
-        self.regs[E1000_STAT].read();
+        self.regs.read(E1000_STAT);
     }
 
     /// Cause a link status change interrupt
     pub fn e1000_cause_lsc_int(&mut self) {
-        self.regs[E1000_ICS].write(E1000_ICR_LSC);
+        self.regs.write(E1000_ICS, E1000_ICR_LSC);
     }
 
-    /// To handle e1000 interrupt
-    pub fn e1000_intr(&mut self) -> u32 {
+    /// To handle e1000 interrupt, returning the causes acknowledged.
+    /// On a link status change the link is re-read, the change then taken by `link_event`.
+    pub fn e1000_intr(&mut self) -> InterruptCause {
         //self.e1000_recv();
 
         // tell the e1000 we've seen this interrupt;
         // without this the e1000 won't raise any
         // further interrupts.
-        let icr = self.regs[E1000_ICR].read();
-        self.regs[E1000_ICR].write(icr); //Writing a 1b to ICR any bit also clears that bit.
-        icr
+        let icr = self.regs.read(E1000_ICR);
+        self.regs.write(E1000_ICR, icr); //Writing a 1b to ICR any bit also clears that bit.
+        if icr & E1000_ICR_LSC != 0 {
+            self.e1000_check_link();
+        }
+        if self.coalesce.itr == Itr::Adaptive {
+            self.e1000_update_itr();
+        }
+        InterruptCause::from_bits_truncate(icr)
+    }
+}
+
+/// Dropping the device quiesces and resets the hardware, then frees the rings and the mbufs.
+/// The buffers of an attached rx pool go back to the pool. Zero-copy tx buffers not
+/// reclaimed yet are lost to the caller, who gets them back with `shutdown` instead.
+impl<'a, K: KernelFunc, R: E1000Regs> Drop for E1000Device<'a, K, R> {
+    fn drop(&mut self) {
+        info!("e1000 shutting down");
+        let lent = self.tx_loans.iter().filter(|buf| buf.is_some()).count() + self.tx_done.len();
+        if lent != 0 {
+            warn!("e1000, dropped with {} zero-copy tx buffers not reclaimed", lent);
+        }
+        if self.e1000_quiesce().is_err() {
+            warn!("e1000, free the DMA memory of a device that may still be running");
+        }
+
+        if let Some(pool) = self.rx_pool.take() {
+            for &index in self.rx_pool_slots.iter() {
+                pool.recycle(index);
+            }
+            self.rx_pool_slots.clear();
+        }
+
+        Self::free_mbufs(&mut self.kfn, &self.tx_mbufs, self.mbuf_size);
+        Self::free_mbufs(&mut self.kfn, &self.rx_mbufs, self.mbuf_size);
+        self.tx_mbufs.clear();
+        self.rx_mbufs.clear();
+
+        let tx_ring_pages = (size_of_val(self.tx_ring) + (K::PAGE_SIZE - 1)) / K::PAGE_SIZE;
+        let rx_ring_pages = (size_of_val(self.rx_ring) + (K::PAGE_SIZE - 1)) / K::PAGE_SIZE;
+        let tx_ring_vaddr = self.tx_ring.as_ptr() as usize;
+        let rx_ring_vaddr = self.rx_ring.as_ptr() as usize;
+        self.kfn.dma_free_coherent(tx_ring_vaddr, tx_ring_pages);
+        self.kfn.dma_free_coherent(rx_ring_vaddr, rx_ring_pages);
     }
 }
 
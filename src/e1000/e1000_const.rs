@@ -2,8 +2,15 @@
 // from the Intel 82540EP/EM &c manual.
 
 /* Registers */
+#[allow(clippy::erasing_op)]
 pub(crate) const E1000_CTL: usize = 0x00000 / 4; /* Device Control Register - RW */
 pub(crate) const E1000_STAT: usize = 0x00008 / 4; /* Device Status Register - R */
+pub(crate) const E1000_EERD: usize = 0x00014 / 4; /* EEPROM Read - RW */
+pub(crate) const E1000_MDIC: usize = 0x00020 / 4; /* MDI Control - RW */
+pub(crate) const E1000_FCAL: usize = 0x00028 / 4; /* Flow Control Address Low - RW */
+pub(crate) const E1000_FCAH: usize = 0x0002C / 4; /* Flow Control Address High -RW */
+pub(crate) const E1000_FCT: usize = 0x00030 / 4; /* Flow Control Type - RW */
+pub(crate) const E1000_VET: usize = 0x00038 / 4; /* VLAN Ether Type - RW */
 pub(crate) const E1000_ICR: usize = 0x000C0 / 4; /* Interrupt Cause Read - R */
 pub(crate) const E1000_ITR: usize = 0x000C4 / 4; /* Interrupt Throttling Rate - RW */
 pub(crate) const E1000_ICS: usize = 0x000C8 / 4; /* Interrupt Cause Set - WO */
@@ -12,6 +19,10 @@ pub(crate) const E1000_IMC: usize = 0x000D8 / 4; /* Interrupt Mask Clear - WO */
 pub(crate) const E1000_RCTL: usize = 0x00100 / 4; /* RX Control - RW */
 pub(crate) const E1000_TCTL: usize = 0x00400 / 4; /* TX Control - RW */
 pub(crate) const E1000_TIPG: usize = 0x00410 / 4; /* TX Inter-packet gap -RW */
+pub(crate) const E1000_FCTTV: usize = 0x00170 / 4; /* TX Flow Control Timer Value - RW */
+pub(crate) const E1000_PBA: usize = 0x01000 / 4; /* Packet Buffer Allocation - RW */
+pub(crate) const E1000_FCRTL: usize = 0x02160 / 4; /* Flow Control Receive Threshold Low - RW */
+pub(crate) const E1000_FCRTH: usize = 0x02168 / 4; /* Flow Control Receive Threshold High - RW */
 pub(crate) const E1000_RDBAL: usize = 0x02800 / 4; /* RX Descriptor Base Address Low - RW */
 pub(crate) const E1000_RDBAH: usize = 0x02804 / 4; /* RX Descriptor Base Address High - RW */
 pub(crate) const E1000_RDTR: usize = 0x02820 / 4; /* RX Delay Timer */
@@ -29,34 +40,189 @@ pub(crate) const E1000_TIDV: usize = 0x03820 / 4; /* TX Interrupt Delay Value -
 pub(crate) const E1000_TADV: usize = 0x0382C / 4; /* TX Interrupt Absolute Delay Val - RW */
 pub(crate) const E1000_MTA: usize = 0x05200 / 4; /* Multicast Table Array - RW Array */
 pub(crate) const E1000_RA: usize = 0x05400 / 4; /* Receive Address Low are used for unicast/multicast address filtering. - RW Array */
+pub(crate) const E1000_VFTA: usize = 0x05600 / 4; /* VLAN Filter Table Array - RW Array */
+pub(crate) const E1000_RXCSUM: usize = 0x05000 / 4; /* RX Checksum Control - RW */
 
 pub(crate) const E1000_RFCTL: usize = 0x05008 / 4; /* e1000e: RFCTL */
 
-/* This defines the bits that are set in the Interrupt Mask
- * Set/Read Register.  Each bit is documented below:
- *   o RXT0   = Receiver Timer Interrupt (ring 0)
- *   o TXDW   = Transmit Descriptor Written Back
- *   o RXDMT0 = Receive Descriptor Minimum Threshold hit (ring 0)
- *   o RXSEQ  = Receive Sequence Error
- *   o LSC    = Link Status Change
- */
-pub(crate) const IMS_ENABLE_MASK: u32 = E1000_IMS_RXT0 /* | E1000_IMS_RXDMT0 | E1000_IMS_RXSEQ */
-    | E1000_IMS_LSC /* | E1000_IMS_TXQE | E1000_IMS_TXDW */;
+/* Statistics registers, clear on read [E1000 13.7] */
+pub(crate) const E1000_CRCERRS: usize = 0x04000 / 4; /* CRC Error Count - R/clr */
+pub(crate) const E1000_ALGNERRC: usize = 0x04004 / 4; /* Alignment Error Count - R/clr */
+pub(crate) const E1000_SYMERRS: usize = 0x04008 / 4; /* Symbol Error Count - R/clr */
+pub(crate) const E1000_RXERRC: usize = 0x0400C / 4; /* Receive Error Count - R/clr */
+pub(crate) const E1000_MPC: usize = 0x04010 / 4; /* Missed Packets Count - R/clr */
+pub(crate) const E1000_SCC: usize = 0x04014 / 4; /* Single Collision Count - R/clr */
+pub(crate) const E1000_ECOL: usize = 0x04018 / 4; /* Excessive Collisions Count - R/clr */
+pub(crate) const E1000_MCC: usize = 0x0401C / 4; /* Multiple Collision Count - R/clr */
+pub(crate) const E1000_LATECOL: usize = 0x04020 / 4; /* Late Collisions Count - R/clr */
+pub(crate) const E1000_COLC: usize = 0x04028 / 4; /* Collision Count - R/clr */
+pub(crate) const E1000_DC: usize = 0x04030 / 4; /* Defer Count - R/clr */
+pub(crate) const E1000_TNCRS: usize = 0x04034 / 4; /* Transmit with No CRS - R/clr */
+pub(crate) const E1000_SEC: usize = 0x04038 / 4; /* Sequence Error Count - R/clr */
+pub(crate) const E1000_CEXTERR: usize = 0x0403C / 4; /* Carrier Extension Error Count - R/clr */
+pub(crate) const E1000_RLEC: usize = 0x04040 / 4; /* Receive Length Error Count - R/clr */
+pub(crate) const E1000_XONRXC: usize = 0x04048 / 4; /* XON Received Count - R/clr */
+pub(crate) const E1000_XONTXC: usize = 0x0404C / 4; /* XON Transmitted Count - R/clr */
+pub(crate) const E1000_XOFFRXC: usize = 0x04050 / 4; /* XOFF Received Count - R/clr */
+pub(crate) const E1000_XOFFTXC: usize = 0x04054 / 4; /* XOFF Transmitted Count - R/clr */
+pub(crate) const E1000_FCRUC: usize = 0x04058 / 4; /* FC Received Unsupported Count - R/clr */
+pub(crate) const E1000_PRC64: usize = 0x0405C / 4; /* Packets Received (64 Bytes) Count - R/clr */
+pub(crate) const E1000_PRC127: usize = 0x04060 / 4; /* Packets Received (65-127 Bytes) Count - R/clr */
+pub(crate) const E1000_PRC255: usize = 0x04064 / 4; /* Packets Received (128-255 Bytes) Count - R/clr */
+pub(crate) const E1000_PRC511: usize = 0x04068 / 4; /* Packets Received (256-511 Bytes) Count - R/clr */
+pub(crate) const E1000_PRC1023: usize = 0x0406C / 4; /* Packets Received (512-1023 Bytes) Count - R/clr */
+pub(crate) const E1000_PRC1522: usize = 0x04070 / 4; /* Packets Received (1024-Max Bytes) Count - R/clr */
+pub(crate) const E1000_GPRC: usize = 0x04074 / 4; /* Good Packets Received Count - R/clr */
+pub(crate) const E1000_BPRC: usize = 0x04078 / 4; /* Broadcast Packets Received Count - R/clr */
+pub(crate) const E1000_MPRC: usize = 0x0407C / 4; /* Multicast Packets Received Count - R/clr */
+pub(crate) const E1000_GPTC: usize = 0x04080 / 4; /* Good Packets Transmitted Count - R/clr */
+pub(crate) const E1000_GORCL: usize = 0x04088 / 4; /* Good Octets Received Count Low - R/clr */
+pub(crate) const E1000_GORCH: usize = 0x0408C / 4; /* Good Octets Received Count High, clears both - R/clr */
+pub(crate) const E1000_GOTCL: usize = 0x04090 / 4; /* Good Octets Transmitted Count Low - R/clr */
+pub(crate) const E1000_GOTCH: usize = 0x04094 / 4; /* Good Octets Transmitted Count High, clears both - R/clr */
+pub(crate) const E1000_RNBC: usize = 0x040A0 / 4; /* Receive No Buffers Count - R/clr */
+pub(crate) const E1000_RUC: usize = 0x040A4 / 4; /* Receive Undersize Count - R/clr */
+pub(crate) const E1000_RFC: usize = 0x040A8 / 4; /* Receive Fragment Count - R/clr */
+pub(crate) const E1000_ROC: usize = 0x040AC / 4; /* Receive Oversize Count - R/clr */
+pub(crate) const E1000_RJC: usize = 0x040B0 / 4; /* Receive Jabber Count - R/clr */
+pub(crate) const E1000_MGTPRC: usize = 0x040B4 / 4; /* Management Packets Received Count - R/clr */
+pub(crate) const E1000_MGTPDC: usize = 0x040B8 / 4; /* Management Packets Dropped Count - R/clr */
+pub(crate) const E1000_MGTPTC: usize = 0x040BC / 4; /* Management Packets Transmitted Count - R/clr */
+pub(crate) const E1000_TORL: usize = 0x040C0 / 4; /* Total Octets Received Low - R/clr */
+pub(crate) const E1000_TORH: usize = 0x040C4 / 4; /* Total Octets Received High, clears both - R/clr */
+pub(crate) const E1000_TOTL: usize = 0x040C8 / 4; /* Total Octets Transmitted Low - R/clr */
+pub(crate) const E1000_TOTH: usize = 0x040CC / 4; /* Total Octets Transmitted High, clears both - R/clr */
+pub(crate) const E1000_TPR: usize = 0x040D0 / 4; /* Total Packets Received - R/clr */
+pub(crate) const E1000_TPT: usize = 0x040D4 / 4; /* Total Packets Transmitted - R/clr */
+pub(crate) const E1000_PTC64: usize = 0x040D8 / 4; /* Packets Transmitted (64 Bytes) Count - R/clr */
+pub(crate) const E1000_PTC127: usize = 0x040DC / 4; /* Packets Transmitted (65-127 Bytes) Count - R/clr */
+pub(crate) const E1000_PTC255: usize = 0x040E0 / 4; /* Packets Transmitted (128-255 Bytes) Count - R/clr */
+pub(crate) const E1000_PTC511: usize = 0x040E4 / 4; /* Packets Transmitted (256-511 Bytes) Count - R/clr */
+pub(crate) const E1000_PTC1023: usize = 0x040E8 / 4; /* Packets Transmitted (512-1023 Bytes) Count - R/clr */
+pub(crate) const E1000_PTC1522: usize = 0x040EC / 4; /* Packets Transmitted (1024-Max Bytes) Count - R/clr */
+pub(crate) const E1000_MPTC: usize = 0x040F0 / 4; /* Multicast Packets Transmitted Count - R/clr */
+pub(crate) const E1000_BPTC: usize = 0x040F4 / 4; /* Broadcast Packets Transmitted Count - R/clr */
+pub(crate) const E1000_TSCTC: usize = 0x040F8 / 4; /* TCP Segmentation Context Transmitted Count - R/clr */
+pub(crate) const E1000_TSCTFC: usize = 0x040FC / 4; /* TCP Segmentation Context Tx Fail Count - R/clr */
 
+/* Interrupt Mask Set/Read, the bits enabled are chosen through `InterruptCause` */
 pub(crate) const E1000_IMS_TXDW: u32 = 0x00000001;
 pub(crate) const E1000_IMS_TXQE: u32 = 0x00000002;
 pub(crate) const E1000_IMS_LSC: u32 = 0x00000004;
 pub(crate) const E1000_IMS_RXSEQ: u32 = 0x00000008;
 pub(crate) const E1000_IMS_RXDMT0: u32 = 0x00000010;
+pub(crate) const E1000_IMS_RXO: u32 = 0x00000040;
 pub(crate) const E1000_IMS_RXT0: u32 = 0x00000080;
 
+/* Interrupt Cause Read */
+pub(crate) const E1000_ICR_TXDW: u32 = 0x00000001; /* Transmit desc written back */
+pub(crate) const E1000_ICR_TXQE: u32 = 0x00000002; /* Transmit Queue empty */
 pub(crate) const E1000_ICR_LSC: u32 = 0x00000004; /* Link Status Change */
+pub(crate) const E1000_ICR_RXSEQ: u32 = 0x00000008; /* rx sequence error */
+pub(crate) const E1000_ICR_RXDMT0: u32 = 0x00000010; /* rx desc min. threshold (0) */
+pub(crate) const E1000_ICR_RXO: u32 = 0x00000040; /* rx overrun */
+pub(crate) const E1000_ICR_RXT0: u32 = 0x00000080; /* rx timer intr (ring 0) */
+
+/* EEPROM Read */
+pub(crate) const E1000_EEPROM_RW_REG_START: u32 = 0x00000001; /* First bit for telling part to start operation */
+pub(crate) const E1000_EEPROM_RW_REG_DONE: u32 = 0x00000010; /* Offset to READ/WRITE done bit */
+pub(crate) const E1000_EEPROM_RW_ADDR_SHIFT: u32 = 8; /* Shift to the address bits */
+pub(crate) const E1000_EEPROM_RW_REG_DATA: u32 = 16; /* Offset to data in EEPROM read/write registers */
+pub(crate) const E1000_EEPROM_READ_TIMEOUT: usize = 100000; /* Polls of EERD before giving up */
+pub(crate) const E1000_RESET_TIMEOUT: usize = 100000; /* Polls of CTRL.RST before giving up */
+pub(crate) const E1000_TX_DRAIN_TIMEOUT: usize = 100000; /* Polls of TDH before giving up */
+
+/* Flow Control [E1000 13.4.5, 13.4.6, 13.4.12] */
+pub(crate) const FLOW_CONTROL_ADDRESS_LOW: u32 = 0x00C28001; /* 01:80:C2:00:00:01 */
+pub(crate) const FLOW_CONTROL_ADDRESS_HIGH: u32 = 0x00000100;
+pub(crate) const FLOW_CONTROL_TYPE: u32 = 0x8808; /* MAC control frame */
+pub(crate) const E1000_FC_PAUSE_TIME: u16 = 0x0680; /* Pause time in 512-bit times */
+pub(crate) const E1000_FCRTL_XONE: u32 = 0x80000000; /* Enable XON frame transmission */
+pub(crate) const E1000_FCRT_MASK: u32 = 0x0000FFF8; /* Thresholds are in 8-byte units */
+pub(crate) const E1000_PBA_RX_MASK: u32 = 0x0000FFFF; /* Receive packet buffer in KB */
+
+/* MDI Control [E1000 13.4.7] */
+pub(crate) const E1000_MDIC_DATA_MASK: u32 = 0x0000FFFF;
+pub(crate) const E1000_MDIC_REG_SHIFT: u32 = 16;
+pub(crate) const E1000_MDIC_PHY_SHIFT: u32 = 21;
+pub(crate) const E1000_MDIC_OP_WRITE: u32 = 0x04000000;
+pub(crate) const E1000_MDIC_OP_READ: u32 = 0x08000000;
+pub(crate) const E1000_MDIC_READY: u32 = 0x10000000;
+pub(crate) const E1000_MDIC_ERROR: u32 = 0x40000000;
+pub(crate) const E1000_MDIC_TIMEOUT: usize = 100000; /* Polls of MDIC.R before giving up */
+pub(crate) const E1000_PHY_RESET_TIMEOUT: usize = 1000; /* Polls of PHY_CTRL.RESET before giving up */
+pub(crate) const E1000_PHY_ADDRESS: u32 = 1; /* Address of the internal PHY */
+pub(crate) const E1000_PHY_REGS: u32 = 32;
+
+/* PHY registers [E1000 13.5] */
+pub(crate) const PHY_CTRL: u32 = 0x00; /* Control Register */
+pub(crate) const PHY_STATUS: u32 = 0x01; /* Status Register */
+pub(crate) const PHY_AUTONEG_ADV: u32 = 0x04; /* Autoneg Advertisement */
+pub(crate) const PHY_LP_ABILITY: u32 = 0x05; /* Link Partner Ability (Base Page) */
+pub(crate) const PHY_1000T_CTRL: u32 = 0x09; /* 1000Base-T Control Reg */
+pub(crate) const PHY_1000T_STATUS: u32 = 0x0A; /* 1000Base-T Status Reg */
+
+/* PHY Control Register */
+pub(crate) const MII_CR_SPEED_SELECT_MSB: u16 = 0x0040; /* bits 6,13: 10=1000, 01=100, 00=10 */
+pub(crate) const MII_CR_FULL_DUPLEX: u16 = 0x0100; /* FDX =1, half duplex =0 */
+pub(crate) const MII_CR_RESTART_AUTO_NEG: u16 = 0x0200; /* Restart auto negotiation */
+pub(crate) const MII_CR_AUTO_NEG_EN: u16 = 0x1000; /* Auto Neg Enable */
+pub(crate) const MII_CR_SPEED_SELECT_LSB: u16 = 0x2000; /* bits 6,13: 10=1000, 01=100, 00=10 */
+pub(crate) const MII_CR_RESET: u16 = 0x8000; /* 0 = normal, 1 = PHY reset */
+pub(crate) const MII_CR_SPEED_MASK: u16 = MII_CR_SPEED_SELECT_MSB | MII_CR_SPEED_SELECT_LSB;
+
+/* PHY Status Register */
+pub(crate) const MII_SR_LINK_STATUS: u16 = 0x0004; /* Link Status 1 = link */
+pub(crate) const MII_SR_AUTONEG_COMPLETE: u16 = 0x0020; /* Auto Neg Complete */
+
+/* Autoneg Advertisement and Link Partner Ability Registers */
+pub(crate) const NWAY_AR_SELECTOR_802_3: u16 = 0x0001; /* IEEE 802.3 CSMA/CD */
+pub(crate) const NWAY_AR_10T_HD_CAPS: u16 = 0x0020; /* 10T   Half Duplex Capable */
+pub(crate) const NWAY_AR_10T_FD_CAPS: u16 = 0x0040; /* 10T   Full Duplex Capable */
+pub(crate) const NWAY_AR_100TX_HD_CAPS: u16 = 0x0080; /* 100TX Half Duplex Capable */
+pub(crate) const NWAY_AR_100TX_FD_CAPS: u16 = 0x0100; /* 100TX Full Duplex Capable */
+pub(crate) const NWAY_AR_PAUSE: u16 = 0x0400; /* Pause operation desired */
+pub(crate) const NWAY_AR_ASM_DIR: u16 = 0x0800; /* Asymmetric Pause Direction bit */
+
+/* 1000BASE-T Control and Status Registers */
+pub(crate) const CR_1000T_FD_CAPS: u16 = 0x0200; /* Advertise 1000T FD capability */
+pub(crate) const SR_1000T_LP_FD_CAPS: u16 = 0x0800; /* LP is 1000T FD capable */
+
+/* EEPROM words */
+pub(crate) const EEPROM_NODE_ADDRESS_BYTE_0: u16 = 0x0000; /* Ethernet address, 3 words */
+pub(crate) const EEPROM_CHECKSUM_REG: u16 = 0x003F;
+pub(crate) const EEPROM_SUM: u16 = 0xBABA; /* Sum of words 0x00 ~ 0x3F */
+
+/* Multicast Table Array */
+pub(crate) const E1000_MTA_ENTRIES: usize = 4096 / 32; /* 4096-bit hash table */
+
+/* Receive Address */
+pub(crate) const E1000_RAR_ENTRIES: usize = 16; /* RAL/RAH pairs */
+pub(crate) const E1000_RAH_AS_SHIFT: u32 = 16; /* Address select */
+pub(crate) const E1000_RAH_AS_MASK: u32 = 0x00030000;
+pub(crate) const E1000_RAH_AV: u32 = 0x80000000; /* Receive descriptor valid */
+
+/* Device Status */
+pub(crate) const E1000_STATUS_FD: u32 = 0x00000001; /* Full duplex.0=half,1=full */
+pub(crate) const E1000_STATUS_LU: u32 = 0x00000002; /* Link up.0=no,1=link */
+pub(crate) const E1000_STATUS_SPEED_10: u32 = 0x00000000; /* Speed 10Mb/s */
+pub(crate) const E1000_STATUS_SPEED_100: u32 = 0x00000040; /* Speed 100Mb/s */
+pub(crate) const E1000_STATUS_SPEED_1000: u32 = 0x00000080; /* Speed 1000Mb/s */
+pub(crate) const E1000_STATUS_SPEED_MASK: u32 = 0x000000C0;
 
 /* Device Control */
+pub(crate) const E1000_CTL_FD: u32 = 0x00000001; /* Full duplex.0=half; 1=full */
+pub(crate) const E1000_CTL_ASDE: u32 = 0x00000020; /* Auto-speed detect enable */
 pub(crate) const E1000_CTL_SLU: u32 = 0x00000040; /* set link up */
+pub(crate) const E1000_CTL_SPD_SEL: u32 = 0x00000300; /* Speed Select Mask */
+pub(crate) const E1000_CTL_SPD_10: u32 = 0x00000000; /* Force 10Mb */
+pub(crate) const E1000_CTL_SPD_100: u32 = 0x00000100; /* Force 100Mb */
 pub(crate) const E1000_CTL_FRCSPD: u32 = 0x00000800; /* force speed */
 pub(crate) const E1000_CTL_FRCDPLX: u32 = 0x00001000; /* force duplex */
 pub(crate) const E1000_CTL_RST: u32 = (1 << 26); /* Device Reset */
+pub(crate) const E1000_CTL_RFCE: u32 = 0x08000000; /* Receive Flow Control enable */
+pub(crate) const E1000_CTL_TFCE: u32 = 0x10000000; /* Transmit flow control enable */
+pub(crate) const E1000_CTL_VME: u32 = 0x40000000; /* IEEE VLAN mode enable */
 
 /* Transmit Control */
 pub(crate) const E1000_TCTL_RST: u32 = 0x00000001; /* software reset */
@@ -94,6 +260,7 @@ pub(crate) const E1000_RCTL_MO_0: u32 = 0x00000000; /* multicast offset 11:0 */
 pub(crate) const E1000_RCTL_MO_1: u32 = 0x00001000; /* multicast offset 12:1 */
 pub(crate) const E1000_RCTL_MO_2: u32 = 0x00002000; /* multicast offset 13:2 */
 pub(crate) const E1000_RCTL_MO_3: u32 = 0x00003000; /* multicast offset 15:4 */
+pub(crate) const E1000_RCTL_MO_MASK: u32 = 0x00003000; /* multicast offset bits */
 pub(crate) const E1000_RCTL_MDR: u32 = 0x00004000; /* multicast desc ring 0 */
 pub(crate) const E1000_RCTL_BAM: u32 = 0x00008000; /* broadcast enable */
 /* these buffer sizes are valid if E1000_RCTL_BSEX is 0 */
@@ -105,6 +272,7 @@ pub(crate) const E1000_RCTL_SZ_256: u32 = 0x00030000; /* rx buffer size 256 */
 pub(crate) const E1000_RCTL_SZ_16384: u32 = 0x00010000; /* rx buffer size 16384 */
 pub(crate) const E1000_RCTL_SZ_8192: u32 = 0x00020000; /* rx buffer size 8192 */
 pub(crate) const E1000_RCTL_SZ_4096: u32 = 0x00030000; /* rx buffer size 4096 */
+pub(crate) const E1000_RCTL_SZ_MASK: u32 = 0x00030000; /* rx buffer size bits */
 pub(crate) const E1000_RCTL_VFE: u32 = 0x00040000; /* vlan filter enable */
 pub(crate) const E1000_RCTL_CFIEN: u32 = 0x00080000; /* canonical form enable */
 pub(crate) const E1000_RCTL_CFI: u32 = 0x00100000; /* canonical form indicator */
@@ -120,6 +288,22 @@ pub(crate) const DATA_MAX: u32 = 1518;
 /* Transmit Descriptor command definitions [E1000 3.3.3.1] */
 pub(crate) const E1000_TXD_CMD_EOP: u32 = 0x01; /* End of Packet */
 pub(crate) const E1000_TXD_CMD_RS: u32 = 0x08; /* Report Status */
+pub(crate) const E1000_TXD_CMD_VLE: u32 = 0x40; /* Add VLAN tag */
+pub(crate) const E1000_TXD_CMD_IDE: u32 = 0x80; /* Enable Tidv register */
+pub(crate) const E1000_TXD_CMD_IFCS: u32 = 0x02; /* Insert FCS (Ethernet CRC) */
+pub(crate) const E1000_TXD_CMD_DEXT: u32 = 0x20; /* Descriptor extension (0 = legacy) */
+pub(crate) const E1000_TXD_CMD_SHIFT: u32 = 24; /* Position of the command byte in an extended descriptor */
+
+/* TCP/IP context and data descriptors [E1000 3.3.6, 3.3.7] */
+pub(crate) const E1000_TXD_DTYP_C: u32 = 0x00000000; /* Context Descriptor */
+pub(crate) const E1000_TXD_DTYP_D: u32 = 0x00100000; /* Data Descriptor */
+pub(crate) const E1000_TXD_DTYP_MASK: u32 = 0x00F00000; /* Descriptor type */
+pub(crate) const E1000_TXD_LEN_MASK: u32 = 0x000FFFFF; /* Data/payload length */
+pub(crate) const E1000_TXD_TUCMD_TCP: u32 = 0x01; /* TCP packet (0 = UDP) */
+pub(crate) const E1000_TXD_TUCMD_IP: u32 = 0x02; /* IPv4 packet (0 = IPv6) */
+pub(crate) const E1000_TXD_CMD_TSE: u32 = 0x04; /* TCP Segmentation Enable, in TUCMD and DCMD */
+pub(crate) const E1000_TXD_POPTS_IXSM: u8 = 0x01; /* Insert IP checksum */
+pub(crate) const E1000_TXD_POPTS_TXSM: u8 = 0x02; /* Insert TCP/UDP checksum */
 
 /* Transmit Descriptor status definitions [E1000 3.3.3.2] */
 pub(crate) const E1000_TXD_STAT_DD: u32 = 0x00000001; /* Descriptor Done */
@@ -127,3 +311,26 @@ pub(crate) const E1000_TXD_STAT_DD: u32 = 0x00000001; /* Descriptor Done */
 /* Receive Descriptor bit definitions [E1000 3.2.3.1] */
 pub(crate) const E1000_RXD_STAT_DD: u32 = 0x01; /* Descriptor Done */
 pub(crate) const E1000_RXD_STAT_EOP: u32 = 0x02; /* End of Packet */
+pub(crate) const E1000_RXD_STAT_VP: u32 = 0x08; /* IEEE VLAN Packet */
+pub(crate) const E1000_RXD_STAT_IXSM: u32 = 0x04; /* Ignore checksum */
+pub(crate) const E1000_RXD_STAT_TCPCS: u32 = 0x20; /* TCP/UDP checksum calculated */
+pub(crate) const E1000_RXD_STAT_IPCS: u32 = 0x40; /* IP checksum calculated */
+pub(crate) const E1000_RXD_ERR_CE: u32 = 0x01; /* CRC Error */
+pub(crate) const E1000_RXD_ERR_SE: u32 = 0x02; /* Symbol Error */
+pub(crate) const E1000_RXD_ERR_SEQ: u32 = 0x04; /* Sequence Error */
+pub(crate) const E1000_RXD_ERR_CXE: u32 = 0x10; /* Carrier Extension Error */
+pub(crate) const E1000_RXD_ERR_TCPE: u32 = 0x20; /* TCP/UDP Checksum Error */
+pub(crate) const E1000_RXD_ERR_IPE: u32 = 0x40; /* IP Checksum Error */
+pub(crate) const E1000_RXD_ERR_RXE: u32 = 0x80; /* Rx Data Error */
+
+/* Receive Checksum Control [E1000 13.4.26] */
+pub(crate) const E1000_RXCSUM_PCSS_MASK: u32 = 0x000000FF; /* Packet Checksum Start */
+pub(crate) const E1000_RXCSUM_IPOFLD: u32 = 0x00000100; /* IPv4 checksum offload */
+pub(crate) const E1000_RXCSUM_TUOFLD: u32 = 0x00000200; /* TCP / UDP checksum offload */
+
+/* 802.1Q VLAN */
+pub(crate) const ETH_P_8021Q: u16 = 0x8100; /* 802.1Q VLAN Extended Header */
+pub(crate) const ETH_HLEN: u32 = 14; /* Length of an untagged Ethernet header */
+pub(crate) const VLAN_HLEN: u32 = 4; /* Length of an 802.1Q tag */
+pub(crate) const E1000_VLAN_FILTER_TBL_SIZE: usize = 128; /* VLAN Filter Table (4096 bits) */
+pub(crate) const VLAN_VID_MASK: u16 = 0x0FFF; /* VLAN Identifier */
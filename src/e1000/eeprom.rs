@@ -0,0 +1,48 @@
+// EEPROM access through the EERD register [E1000 5.3.1, 13.4.4]
+use super::e1000_const::*;
+use super::error::E1000Error;
+use super::regs::E1000Regs;
+
+/// Read a 16-bit word of the EEPROM
+pub fn eeprom_read_word<R: E1000Regs>(regs: &mut R, offset: u16) -> Result<u16, E1000Error> {
+    regs.write(
+        E1000_EERD,
+        ((offset as u32) << E1000_EEPROM_RW_ADDR_SHIFT) | E1000_EEPROM_RW_REG_START,
+    );
+
+    for _ in 0..E1000_EEPROM_READ_TIMEOUT {
+        let eerd = regs.read(E1000_EERD);
+        if eerd & E1000_EEPROM_RW_REG_DONE != 0 {
+            return Ok((eerd >> E1000_EEPROM_RW_REG_DATA) as u16);
+        }
+    }
+    error!("e1000, EEPROM read of word {:#x} timed out", offset);
+    Err(E1000Error::EepromTimeout)
+}
+
+/// Check that the words 0x00 ~ 0x3F of the EEPROM sum up to 0xBABA
+pub fn eeprom_validate_checksum<R: E1000Regs>(regs: &mut R) -> Result<(), E1000Error> {
+    let mut checksum: u16 = 0;
+    for offset in 0..=EEPROM_CHECKSUM_REG {
+        checksum = checksum.wrapping_add(eeprom_read_word(regs, offset)?);
+    }
+
+    if checksum != EEPROM_SUM {
+        error!("e1000, EEPROM checksum is invalid: {:#x}", checksum);
+        return Err(E1000Error::EepromChecksum);
+    }
+    Ok(())
+}
+
+/// Read the permanent MAC address burned into the EEPROM, after validating its checksum
+pub fn read_mac_address<R: E1000Regs>(regs: &mut R) -> Result<[u8; 6], E1000Error> {
+    eeprom_validate_checksum(regs)?;
+
+    let mut mac = [0u8; 6];
+    for (i, bytes) in mac.chunks_mut(2).enumerate() {
+        let word = eeprom_read_word(regs, EEPROM_NODE_ADDRESS_BYTE_0 + i as u16)?;
+        bytes[0] = word as u8;
+        bytes[1] = (word >> 8) as u8;
+    }
+    Ok(mac)
+}
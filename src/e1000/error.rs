@@ -0,0 +1,54 @@
+// Errors of the e1000 driver
+use core::fmt;
+
+/// Why an operation of the driver failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum E1000Error {
+    /// DMA memory for the rings or the buffers couldn't be allocated
+    AllocFailed,
+    /// The hardware hasn't finished with the tx descriptors needed yet, try again later
+    RingFull,
+    /// The frame is larger than the MTU allows, or needs more descriptors than the ring has
+    FrameTooLarge,
+    /// Sizes the hardware doesn't support, or a setting the current state of the device rules out
+    InvalidConfig,
+    /// An argument out of range, e.g. a multicast address as unicast filter or VLAN ID 4096
+    InvalidParam,
+    /// No free entry left in the receive address table
+    FilterFull,
+    /// The address or ID to remove was never added
+    NotFound,
+    /// The EEPROM didn't answer a read
+    EepromTimeout,
+    /// The EEPROM words don't sum up to 0xBABA
+    EepromChecksum,
+    /// The device didn't come out of reset
+    ResetTimeout,
+    /// The PHY didn't answer an MDI access
+    PhyTimeout,
+    /// The PHY reported an error for an MDI read
+    PhyError,
+    /// There is no link
+    LinkDown,
+}
+
+impl fmt::Display for E1000Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            E1000Error::AllocFailed => "DMA allocation failed",
+            E1000Error::RingFull => "tx ring full",
+            E1000Error::FrameTooLarge => "frame too large",
+            E1000Error::InvalidConfig => "invalid configuration",
+            E1000Error::InvalidParam => "invalid parameter",
+            E1000Error::FilterFull => "receive address table full",
+            E1000Error::NotFound => "no such filter entry",
+            E1000Error::EepromTimeout => "EEPROM read timed out",
+            E1000Error::EepromChecksum => "invalid EEPROM checksum",
+            E1000Error::ResetTimeout => "reset timed out",
+            E1000Error::PhyTimeout => "PHY access timed out",
+            E1000Error::PhyError => "PHY access failed",
+            E1000Error::LinkDown => "link down",
+        };
+        f.write_str(msg)
+    }
+}
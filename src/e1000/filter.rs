@@ -0,0 +1,316 @@
+// Receive address filtering [E1000 13.5.1, 13.5.2]
+use super::e1000::{E1000Device, KernelFunc};
+use super::e1000_const::*;
+use super::error::E1000Error;
+use super::regs::E1000Regs;
+use super::super::Ext;
+use alloc::vec::Vec;
+
+/// Which address of a received packet is compared with a Receive Address entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSelect {
+    /// Match the destination address (the normal unicast filter)
+    Destination = 0b00,
+    /// Match the source address
+    Source = 0b01,
+}
+
+/// A valid entry of the Receive Address registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceiveAddress {
+    /// Index of the RAL/RAH pair, 0 is the primary MAC address
+    pub index: usize,
+    pub mac: [u8; 6],
+    pub select: AddressSelect,
+}
+
+/// Which 12 bits of a multicast address index the Multicast Table Array (RCTL.MO)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MulticastOffset {
+    /// Address bits [47:36]
+    Bits47_36 = 0b00,
+    /// Address bits [46:35]
+    Bits46_35 = 0b01,
+    /// Address bits [45:34]
+    Bits45_34 = 0b10,
+    /// Address bits [43:32]
+    Bits43_32 = 0b11,
+}
+
+impl MulticastOffset {
+    /// The 12-bit MTA hash of a multicast address
+    pub fn hash(self, mac: &[u8; 6]) -> u16 {
+        let (low, high) = (mac[4] as u16, mac[5] as u16);
+        let hash = match self {
+            MulticastOffset::Bits47_36 => (low >> 4) | (high << 4),
+            MulticastOffset::Bits46_35 => (low >> 3) | (high << 5),
+            MulticastOffset::Bits45_34 => (low >> 2) | (high << 6),
+            MulticastOffset::Bits43_32 => low | (high << 8),
+        };
+        hash & 0xFFF
+    }
+}
+
+/// Which packets the receiver accepts beyond the address filters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RxMode {
+    /// Unicast and multicast filters, plus broadcast
+    Normal,
+    /// Every multicast packet, unicast still filtered (RCTL.MPE)
+    AllMulticast,
+    /// Every good packet (RCTL.UPE and RCTL.MPE)
+    Promiscuous,
+    /// Every packet, including those with CRC, symbol or alignment errors (plus RCTL.SBP)
+    PromiscuousBadPackets,
+}
+
+impl RxMode {
+    /// RCTL bits of the mode
+    fn rctl_bits(self) -> u32 {
+        match self {
+            RxMode::Normal => 0,
+            RxMode::AllMulticast => E1000_RCTL_MPE,
+            RxMode::Promiscuous => E1000_RCTL_UPE | E1000_RCTL_MPE,
+            RxMode::PromiscuousBadPackets => E1000_RCTL_UPE | E1000_RCTL_MPE | E1000_RCTL_SBP,
+        }
+    }
+}
+
+pub(super) fn is_multicast(mac: &[u8; 6]) -> bool {
+    mac[0] & 0x01 != 0
+}
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Program the Receive Address pair `index`, and mark it valid
+    pub(crate) fn e1000_rar_set(&mut self, index: usize, mac: [u8; 6], select: AddressSelect) {
+        let ral = u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]);
+        let rah = u16::from_le_bytes([mac[4], mac[5]]) as u32
+            | ((select as u32) << E1000_RAH_AS_SHIFT)
+            | E1000_RAH_AV;
+
+        // Clear AV first, so the pair is never valid with half an address
+        self.regs.write(E1000_RA + index * 2 + 1, 0);
+        self.regs.write(E1000_RA + index * 2, ral);
+        self.regs.write(E1000_RA + index * 2 + 1, rah);
+        self.e1000_write_flush();
+    }
+
+    /// Invalidate the Receive Address pair `index`
+    pub(crate) fn e1000_rar_clear(&mut self, index: usize) {
+        self.regs.write(E1000_RA + index * 2 + 1, 0);
+        self.regs.write(E1000_RA + index * 2, 0);
+        self.e1000_write_flush();
+    }
+
+    /// Read back the Receive Address pair `index`, if it's valid
+    pub(crate) fn e1000_rar_get(&self, index: usize) -> Option<ReceiveAddress> {
+        let ral = self.regs.read(E1000_RA + index * 2);
+        let rah = self.regs.read(E1000_RA + index * 2 + 1);
+        if rah & E1000_RAH_AV == 0 {
+            return None;
+        }
+
+        let low = ral.to_le_bytes();
+        let high = (rah as u16).to_le_bytes();
+        let select = match (rah & E1000_RAH_AS_MASK) >> E1000_RAH_AS_SHIFT {
+            0b01 => AddressSelect::Source,
+            _ => AddressSelect::Destination,
+        };
+        Some(ReceiveAddress {
+            index,
+            mac: [low[0], low[1], low[2], low[3], high[0], high[1]],
+            select,
+        })
+    }
+
+    /// Set the primary MAC address, which the device filters on in RA[0]
+    pub fn set_mac_address(&mut self, mac: [u8; 6]) -> Result<(), E1000Error> {
+        if is_multicast(&mac) {
+            error!("e1000, {:02x?} is not a unicast address", mac);
+            return Err(E1000Error::InvalidParam);
+        }
+        self.e1000_rar_set(0, mac, AddressSelect::Destination);
+        Ok(())
+    }
+
+    /// Accept packets for one more unicast address, using a free entry of RA[1..15].
+    /// Returns the index of the entry.
+    pub fn add_unicast_filter(&mut self, mac: [u8; 6], select: AddressSelect) -> Result<usize, E1000Error> {
+        if is_multicast(&mac) {
+            error!("e1000, {:02x?} is not a unicast address", mac);
+            return Err(E1000Error::InvalidParam);
+        }
+
+        let mut free = None;
+        for index in 1..E1000_RAR_ENTRIES {
+            match self.e1000_rar_get(index) {
+                Some(ra) if ra.mac == mac && ra.select == select => return Ok(index),
+                Some(_) => {}
+                None => {
+                    if free.is_none() {
+                        free = Some(index);
+                    }
+                }
+            }
+        }
+
+        match free {
+            Some(index) => {
+                self.e1000_rar_set(index, mac, select);
+                Ok(index)
+            }
+            None => {
+                warn!("e1000, all {} receive addresses are in use", E1000_RAR_ENTRIES);
+                Err(E1000Error::FilterFull)
+            }
+        }
+    }
+
+    /// Stop accepting packets for a unicast address added by `add_unicast_filter`
+    pub fn remove_unicast_filter(&mut self, mac: [u8; 6], select: AddressSelect) -> Result<(), E1000Error> {
+        for index in 1..E1000_RAR_ENTRIES {
+            match self.e1000_rar_get(index) {
+                Some(ra) if ra.mac == mac && ra.select == select => {
+                    self.e1000_rar_clear(index);
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        Err(E1000Error::NotFound)
+    }
+
+    /// The extra unicast filters in RA[1..15]
+    pub fn unicast_filters(&self) -> Vec<ReceiveAddress> {
+        let mut filters = Vec::new();
+        for index in 1..E1000_RAR_ENTRIES {
+            if let Some(ra) = self.e1000_rar_get(index) {
+                filters.push(ra);
+            }
+        }
+        filters
+    }
+}
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Set or clear the bit of an MTA hash bucket
+    fn e1000_mta_set(&mut self, hash: u16, set: bool) {
+        let reg = E1000_MTA + ((hash as usize >> 5) & (E1000_MTA_ENTRIES - 1));
+        let bit = 1 << (hash & 0x1F);
+        let mta = self.regs.read(reg);
+        self.regs.write(reg, if set { mta | bit } else { mta & !bit });
+    }
+
+    /// Take a reference to an MTA bucket, setting its bit for the first one
+    fn e1000_mta_get_ref(&mut self, hash: u16) {
+        match self.mta_refcnt.iter_mut().find(|(h, _)| *h == hash) {
+            Some((_, count)) => *count += 1,
+            None => {
+                self.mta_refcnt.push((hash, 1));
+                self.e1000_mta_set(hash, true);
+            }
+        }
+    }
+
+    /// Drop a reference to an MTA bucket, clearing its bit with the last one
+    fn e1000_mta_put_ref(&mut self, hash: u16) {
+        if let Some(i) = self.mta_refcnt.iter().position(|(h, _)| *h == hash) {
+            self.mta_refcnt[i].1 -= 1;
+            if self.mta_refcnt[i].1 == 0 {
+                self.mta_refcnt.swap_remove(i);
+                self.e1000_mta_set(hash, false);
+            }
+        }
+    }
+
+    /// Rewrite the whole Multicast Table Array from the joined addresses
+    pub(crate) fn e1000_mta_sync(&mut self) {
+        self.mta_refcnt.clear();
+        for i in 0..E1000_MTA_ENTRIES {
+            self.regs.write(E1000_MTA + i, 0);
+        }
+        for i in 0..self.mc_addrs.len() {
+            let hash = self.mc_offset.hash(&self.mc_addrs[i]);
+            self.e1000_mta_get_ref(hash);
+        }
+        self.e1000_write_flush();
+    }
+
+    /// Accept packets sent to a multicast address.
+    /// Each call takes a reference, which `remove_multicast` drops.
+    pub fn add_multicast(&mut self, mac: [u8; 6]) -> Result<(), E1000Error> {
+        if !is_multicast(&mac) {
+            error!("e1000, {:02x?} is not a multicast address", mac);
+            return Err(E1000Error::InvalidParam);
+        }
+        self.mc_addrs.push(mac);
+        self.e1000_mta_get_ref(self.mc_offset.hash(&mac));
+        self.e1000_write_flush();
+        Ok(())
+    }
+
+    /// Drop a reference taken by `add_multicast`.
+    /// The hash bucket keeps accepting packets while other addresses share it.
+    pub fn remove_multicast(&mut self, mac: [u8; 6]) -> Result<(), E1000Error> {
+        let i = self
+            .mc_addrs
+            .iter()
+            .position(|addr| *addr == mac)
+            .ok_or(E1000Error::NotFound)?;
+        self.mc_addrs.swap_remove(i);
+        self.e1000_mta_put_ref(self.mc_offset.hash(&mac));
+        self.e1000_write_flush();
+        Ok(())
+    }
+
+    /// Replace all joined multicast addresses by `list`
+    pub fn set_multicast_list(&mut self, list: &[[u8; 6]]) -> Result<(), E1000Error> {
+        if let Some(mac) = list.iter().find(|mac| !is_multicast(mac)) {
+            error!("e1000, {:02x?} is not a multicast address", mac);
+            return Err(E1000Error::InvalidParam);
+        }
+        self.mc_addrs.clear();
+        for mac in list {
+            self.mc_addrs.push(*mac);
+        }
+        self.e1000_mta_sync();
+        Ok(())
+    }
+
+    /// The joined multicast addresses
+    pub fn multicast_list(&self) -> &[[u8; 6]] {
+        &self.mc_addrs
+    }
+
+    /// Select the address bits hashed into the MTA, and rehash the joined addresses
+    pub fn set_multicast_offset(&mut self, offset: MulticastOffset) {
+        self.mc_offset = offset;
+        let rctl = self.regs.read(E1000_RCTL) & !E1000_RCTL_MO_MASK;
+        self.regs.write(E1000_RCTL, rctl | ((offset as u32) << E1000_RCTL_MO_SHIFT));
+        self.e1000_mta_sync();
+    }
+
+    /// Accept all multicast packets (RCTL.MPE), whatever the MTA says.
+    /// The promiscuous modes already do, and are left as they are.
+    pub fn set_all_multicast(&mut self, enable: bool) {
+        match (self.rx_mode, enable) {
+            (RxMode::Normal, true) => self.set_rx_mode(RxMode::AllMulticast),
+            (RxMode::AllMulticast, false) => self.set_rx_mode(RxMode::Normal),
+            _ => {}
+        }
+    }
+
+    /// Switch the receive mode.
+    /// Only the UPE/MPE/SBP bits of RCTL change, so the receiver keeps running.
+    pub fn set_rx_mode(&mut self, mode: RxMode) {
+        self.rx_mode = mode;
+        let rctl = self.regs.read(E1000_RCTL) & !(E1000_RCTL_UPE | E1000_RCTL_MPE | E1000_RCTL_SBP);
+        self.regs.write(E1000_RCTL, rctl | mode.rctl_bits());
+        self.e1000_write_flush();
+    }
+
+    /// The current receive mode
+    pub fn rx_mode(&self) -> RxMode {
+        self.rx_mode
+    }
+}
@@ -0,0 +1,240 @@
+// IEEE 802.3x flow control: PAUSE frames sent and honored, resolved from auto-negotiation
+// [E1000 3.5, 13.4.5, 13.4.6, 13.4.12]
+use super::e1000::{E1000Device, KernelFunc};
+use super::e1000_const::*;
+use super::error::E1000Error;
+use super::link::{Duplex, LinkState};
+use super::phy::LinkModes;
+use super::regs::E1000Regs;
+use core::cmp::min;
+
+/// Which way PAUSE frames work
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowControl {
+    /// No PAUSE frames
+    None,
+    /// Stop sending when PAUSE frames are received (CTRL.RFCE)
+    RxPause,
+    /// Send PAUSE frames when the receive buffer fills up (CTRL.TFCE)
+    TxPause,
+    /// Both
+    Full,
+}
+
+impl FlowControl {
+    /// The PAUSE bits to advertise for it [IEEE 802.3 Annex 28B]
+    pub(super) fn advertise(self) -> LinkModes {
+        match self {
+            FlowControl::None => LinkModes::empty(),
+            FlowControl::RxPause | FlowControl::Full => LinkModes::PAUSE | LinkModes::ASYM_PAUSE,
+            FlowControl::TxPause => LinkModes::ASYM_PAUSE,
+        }
+    }
+
+    fn ctl_bits(self) -> u32 {
+        match self {
+            FlowControl::None => 0,
+            FlowControl::RxPause => E1000_CTL_RFCE,
+            FlowControl::TxPause => E1000_CTL_TFCE,
+            FlowControl::Full => E1000_CTL_RFCE | E1000_CTL_TFCE,
+        }
+    }
+
+    /// What the link ends up with, given what both ends advertised [IEEE 802.3 Table 28B-3]
+    fn resolve(self, ours: LinkModes, partner: LinkModes) -> FlowControl {
+        let both = LinkModes::PAUSE | LinkModes::ASYM_PAUSE;
+        if ours.contains(LinkModes::PAUSE) && partner.contains(LinkModes::PAUSE) {
+            if self == FlowControl::Full {
+                FlowControl::Full
+            } else {
+                FlowControl::RxPause
+            }
+        } else if !ours.contains(LinkModes::PAUSE)
+            && ours.contains(LinkModes::ASYM_PAUSE)
+            && partner.contains(both)
+        {
+            FlowControl::TxPause
+        } else if ours.contains(both)
+            && !partner.contains(LinkModes::PAUSE)
+            && partner.contains(LinkModes::ASYM_PAUSE)
+        {
+            FlowControl::RxPause
+        } else {
+            FlowControl::None
+        }
+    }
+}
+
+/// Builder of the flow control settings, passed to `E1000Device::set_flow_control`.
+///
+/// ```ignore
+/// dev.set_flow_control(FlowControlConfig::new(FlowControl::Full).pause_time(0x100))?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowControlConfig {
+    pub(super) mode: FlowControl,
+    pub(super) pause_time: u16,
+    pub(super) watermarks: Option<(u32, u32)>,
+    pub(super) send_xon: bool,
+    pub(super) discard_pause: bool,
+    pub(super) pass_mac_control: bool,
+}
+
+impl Default for FlowControlConfig {
+    fn default() -> Self {
+        FlowControlConfig {
+            mode: FlowControl::None,
+            pause_time: E1000_FC_PAUSE_TIME,
+            watermarks: None,
+            send_xon: true,
+            discard_pause: false,
+            pass_mac_control: false,
+        }
+    }
+}
+
+impl FlowControlConfig {
+    /// Request `mode`, which auto-negotiation may narrow down
+    pub fn new(mode: FlowControl) -> Self {
+        FlowControlConfig {
+            mode,
+            ..Self::default()
+        }
+    }
+
+    /// Pause time sent in XOFF frames, in units of 512 bit times
+    pub fn pause_time(mut self, time: u16) -> Self {
+        self.pause_time = time;
+        self
+    }
+
+    /// Fill levels of the receive packet buffer in bytes: XOFF is sent above `high`,
+    /// XON once back below `low`. By default they leave room for a full frame.
+    pub fn watermarks(mut self, low: u32, high: u32) -> Self {
+        self.watermarks = Some((low, high));
+        self
+    }
+
+    /// Whether to send XON when the buffer drains below the low watermark
+    pub fn send_xon(mut self, enable: bool) -> Self {
+        self.send_xon = enable;
+        self
+    }
+
+    /// Drop PAUSE frames instead of passing them up (RCTL.DPF)
+    pub fn discard_pause_frames(mut self, enable: bool) -> Self {
+        self.discard_pause = enable;
+        self
+    }
+
+    /// Pass MAC control frames other than PAUSE up (RCTL.PMCF)
+    pub fn pass_mac_control_frames(mut self, enable: bool) -> Self {
+        self.pass_mac_control = enable;
+        self
+    }
+}
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Request flow control. With auto-negotiation the PAUSE abilities are advertised
+    /// and negotiated again, the outcome applied once the link comes back up;
+    /// with a forced speed and duplex the mode applies as it is.
+    pub fn set_flow_control(&mut self, config: FlowControlConfig) -> Result<(), E1000Error> {
+        if let Some((low, high)) = config.watermarks {
+            if low >= high || high > E1000_FCRT_MASK {
+                error!("e1000, invalid flow control watermarks {}..{}", low, high);
+                return Err(E1000Error::InvalidConfig);
+            }
+        }
+        self.fc = config;
+        self.e1000_fc_setup();
+
+        let phy_ctrl = self.phy_read(PHY_CTRL)?;
+        if phy_ctrl & MII_CR_AUTO_NEG_EN == 0 {
+            let mode = self.e1000_fc_duplex(self.fc.mode, self.link_state());
+            self.e1000_fc_apply(mode);
+            return Ok(());
+        }
+        let adv = self.phy_read(PHY_AUTONEG_ADV)? & !(NWAY_AR_PAUSE | NWAY_AR_ASM_DIR);
+        self.phy_write(PHY_AUTONEG_ADV, adv | self.fc.mode.advertise().to_phy().0)?;
+        self.phy_restart_autoneg()
+    }
+
+    /// Flow control in effect, as set in CTRL.RFCE and CTRL.TFCE
+    pub fn flow_control(&self) -> FlowControl {
+        let ctl = self.regs.read(E1000_CTL);
+        match (ctl & E1000_CTL_RFCE != 0, ctl & E1000_CTL_TFCE != 0) {
+            (false, false) => FlowControl::None,
+            (true, false) => FlowControl::RxPause,
+            (false, true) => FlowControl::TxPause,
+            (true, true) => FlowControl::Full,
+        }
+    }
+
+    /// Program the PAUSE frame address and type, the pause time, the receive
+    /// watermarks and the handling of MAC control frames
+    pub(super) fn e1000_fc_setup(&mut self) {
+        self.regs.write(E1000_FCAL, FLOW_CONTROL_ADDRESS_LOW);
+        self.regs.write(E1000_FCAH, FLOW_CONTROL_ADDRESS_HIGH);
+        self.regs.write(E1000_FCT, FLOW_CONTROL_TYPE);
+        self.regs.write(E1000_FCTTV, self.fc.pause_time as u32);
+
+        if self.fc.mode == FlowControl::TxPause || self.fc.mode == FlowControl::Full {
+            let (low, high) = self.fc.watermarks.unwrap_or_else(|| {
+                // Room for a full frame above the high watermark, as Linux does
+                let rx_buffer = ((self.regs.read(E1000_PBA) & E1000_PBA_RX_MASK) << 10) as usize;
+                let high = min(rx_buffer * 9 / 10, rx_buffer.saturating_sub(self.max_frame_size()));
+                let high = high as u32 & E1000_FCRT_MASK;
+                (high.saturating_sub(8), high)
+            });
+            let xone = if self.fc.send_xon { E1000_FCRTL_XONE } else { 0 };
+            self.regs.write(E1000_FCRTL, (low & E1000_FCRT_MASK) | xone);
+            self.regs.write(E1000_FCRTH, high & E1000_FCRT_MASK);
+        } else {
+            self.regs.write(E1000_FCRTL, 0);
+            self.regs.write(E1000_FCRTH, 0);
+        }
+
+        let mut rctl = self.regs.read(E1000_RCTL) & !(E1000_RCTL_DPF | E1000_RCTL_PMCF);
+        if self.fc.discard_pause {
+            rctl |= E1000_RCTL_DPF;
+        }
+        if self.fc.pass_mac_control {
+            rctl |= E1000_RCTL_PMCF;
+        }
+        self.regs.write(E1000_RCTL, rctl);
+    }
+
+    /// Settle flow control once the link is up: negotiated from both ends'
+    /// PAUSE abilities, or as requested with a forced speed and duplex
+    pub(super) fn e1000_fc_resolve(&mut self, state: LinkState) -> Result<(), E1000Error> {
+        let mode = if self.fc.mode == FlowControl::None {
+            FlowControl::None
+        } else if self.phy_read(PHY_CTRL)? & MII_CR_AUTO_NEG_EN == 0 {
+            self.fc.mode
+        } else {
+            let ours = self.phy_advertised()?;
+            let partner = self.phy_partner_abilities()?;
+            self.fc.mode.resolve(ours, partner)
+        };
+        let mode = self.e1000_fc_duplex(mode, state);
+        if mode != self.flow_control() {
+            info!("e1000 flow control: {:?}", mode);
+        }
+        self.e1000_fc_apply(mode);
+        Ok(())
+    }
+
+    /// PAUSE frames only work in full duplex
+    fn e1000_fc_duplex(&self, mode: FlowControl, state: LinkState) -> FlowControl {
+        if state.duplex == Duplex::Half {
+            FlowControl::None
+        } else {
+            mode
+        }
+    }
+
+    fn e1000_fc_apply(&mut self, mode: FlowControl) {
+        let ctl = self.regs.read(E1000_CTL) & !(E1000_CTL_RFCE | E1000_CTL_TFCE);
+        self.regs.write(E1000_CTL, ctl | mode.ctl_bits());
+    }
+}
@@ -0,0 +1,121 @@
+// Interrupt causes, as read from ICR and masked through IMS/IMC [E1000 13.4.17 ~ 13.4.21]
+use super::e1000::{E1000Device, KernelFunc};
+use super::e1000_const::*;
+use super::regs::E1000Regs;
+use core::ops::{BitAnd, BitOr, BitOrAssign, Not};
+
+/// A set of interrupt causes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterruptCause(u32);
+
+impl InterruptCause {
+    /// Transmit descriptor written back
+    pub const TXDW: InterruptCause = InterruptCause(E1000_ICR_TXDW);
+    /// Transmit queue empty
+    pub const TXQE: InterruptCause = InterruptCause(E1000_ICR_TXQE);
+    /// Link status change
+    pub const LSC: InterruptCause = InterruptCause(E1000_ICR_LSC);
+    /// Receive sequence error
+    pub const RXSEQ: InterruptCause = InterruptCause(E1000_ICR_RXSEQ);
+    /// Receive descriptors down to the minimum threshold (ring 0)
+    pub const RXDMT0: InterruptCause = InterruptCause(E1000_ICR_RXDMT0);
+    /// Receiver overrun, no free descriptor for a packet
+    pub const RXO: InterruptCause = InterruptCause(E1000_ICR_RXO);
+    /// Receiver timer interrupt (ring 0)
+    pub const RXT0: InterruptCause = InterruptCause(E1000_ICR_RXT0);
+    /// Every cause above
+    pub const ALL: InterruptCause = InterruptCause(
+        E1000_ICR_TXDW
+            | E1000_ICR_TXQE
+            | E1000_ICR_LSC
+            | E1000_ICR_RXSEQ
+            | E1000_ICR_RXDMT0
+            | E1000_ICR_RXO
+            | E1000_ICR_RXT0,
+    );
+    /// What `e1000_irq_enable` enables unless the caller chooses otherwise:
+    /// received packets and link changes. Transmitted packets (TXDW) are opt-in,
+    /// every descriptor asking for a write-back.
+    pub const DEFAULT: InterruptCause = InterruptCause(E1000_ICR_RXT0 | E1000_ICR_LSC);
+
+    /// No cause at all
+    pub const fn empty() -> Self {
+        InterruptCause(0)
+    }
+
+    /// The causes among the bits of ICR or IMS, others dropped
+    pub const fn from_bits_truncate(bits: u32) -> Self {
+        InterruptCause(bits & Self::ALL.0)
+    }
+
+    /// The raw bits
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether no cause is set
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether all the causes of `other` are set
+    pub const fn contains(self, other: InterruptCause) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether any cause of `other` is set
+    pub const fn intersects(self, other: InterruptCause) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for InterruptCause {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        InterruptCause(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for InterruptCause {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for InterruptCause {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        InterruptCause(self.0 & rhs.0)
+    }
+}
+
+impl Not for InterruptCause {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        InterruptCause(!self.0 & Self::ALL.0)
+    }
+}
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Unmask the causes of `mask`, adding them to those `e1000_irq_enable` enables
+    pub fn enable_interrupts(&mut self, mask: InterruptCause) {
+        self.irq_mask |= mask;
+        self.regs.write(E1000_IMS, mask.bits());
+        self.e1000_write_flush();
+    }
+
+    /// Mask the causes of `mask`, taking them out of those `e1000_irq_enable` enables
+    pub fn disable_interrupts(&mut self, mask: InterruptCause) {
+        self.irq_mask = self.irq_mask & !mask;
+        self.regs.write(E1000_IMC, mask.bits());
+        self.e1000_write_flush();
+    }
+
+    /// The causes `e1000_irq_enable` enables, `InterruptCause::DEFAULT` unless changed
+    pub fn interrupt_mask(&self) -> InterruptCause {
+        self.irq_mask
+    }
+}
@@ -0,0 +1,112 @@
+// Link status: up/down, speed and duplex from the STATUS register [E1000 13.4.2]
+use super::e1000::{E1000Device, KernelFunc};
+use super::e1000_const::*;
+use super::regs::E1000Regs;
+
+/// Speed the link resolved to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkSpeed {
+    Mbps10,
+    Mbps100,
+    Mbps1000,
+}
+
+impl LinkSpeed {
+    /// The speed in Mb/s
+    pub fn mbps(self) -> u32 {
+        match self {
+            LinkSpeed::Mbps10 => 10,
+            LinkSpeed::Mbps100 => 100,
+            LinkSpeed::Mbps1000 => 1000,
+        }
+    }
+}
+
+/// Duplex the link resolved to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Duplex {
+    Half,
+    Full,
+}
+
+/// State of the link as the MAC sees it.
+/// Speed and duplex are only meaningful while the link is up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkState {
+    pub up: bool,
+    pub speed: LinkSpeed,
+    pub duplex: Duplex,
+}
+
+impl Default for LinkState {
+    fn default() -> Self {
+        LinkState {
+            up: false,
+            speed: LinkSpeed::Mbps10,
+            duplex: Duplex::Half,
+        }
+    }
+}
+
+impl LinkState {
+    /// Decode the LU, SPEED and FD bits of STATUS
+    pub(super) fn from_status(status: u32) -> Self {
+        let speed = match status & E1000_STATUS_SPEED_MASK {
+            E1000_STATUS_SPEED_10 => LinkSpeed::Mbps10,
+            E1000_STATUS_SPEED_100 => LinkSpeed::Mbps100,
+            // 11b also means 1000 Mb/s
+            _ => LinkSpeed::Mbps1000,
+        };
+        let duplex = if status & E1000_STATUS_FD != 0 {
+            Duplex::Full
+        } else {
+            Duplex::Half
+        };
+        LinkState {
+            up: status & E1000_STATUS_LU != 0,
+            speed,
+            duplex,
+        }
+    }
+}
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Read the current link state from the hardware
+    pub fn link_state(&self) -> LinkState {
+        LinkState::from_status(self.regs.read(E1000_STAT))
+    }
+
+    /// The link state as of its last change, reported by `e1000_intr`
+    /// through `link_event`
+    pub fn last_link_state(&self) -> LinkState {
+        self.link
+    }
+
+    /// Take the link change seen by `e1000_intr` since the last call, if any.
+    /// Several changes in between are folded into the latest state.
+    pub fn link_event(&mut self) -> Option<LinkState> {
+        self.link_event.take()
+    }
+
+    /// Re-read STATUS after a link status change interrupt, recording
+    /// an event if the state differs from the last one seen.
+    /// Flow control is settled again whenever the link is up.
+    pub(super) fn e1000_check_link(&mut self) {
+        let state = self.link_state();
+        if state.up {
+            if let Err(err) = self.e1000_fc_resolve(state) {
+                warn!("e1000, flow control not resolved: {}", err);
+            }
+        }
+        if state != self.link {
+            info!(
+                "e1000 link {}, {} Mb/s {:?} duplex",
+                if state.up { "up" } else { "down" },
+                state.speed.mbps(),
+                state.duplex
+            );
+            self.link = state;
+            self.link_event = Some(state);
+        }
+    }
+}
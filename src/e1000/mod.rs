@@ -1,4 +1,46 @@
+mod coalesce;
+mod config;
+#[allow(clippy::module_inception)]
 mod e1000;
 mod e1000_const;
+mod eeprom;
+mod error;
+mod filter;
+mod flowctl;
+mod interrupt;
+mod link;
+mod mtu;
+mod offload;
+mod phy;
+mod poll;
+mod regs;
+mod rxpool;
+mod stats;
+mod txclean;
+mod vlan;
+mod zerocopy;
+#[cfg(any(test, feature = "sim"))]
+mod sim;
 
+#[cfg(test)]
+mod tests;
+
+pub use self::coalesce::*;
+pub use self::config::*;
 pub use self::e1000::*;
+pub use self::eeprom::*;
+pub use self::error::*;
+pub use self::filter::*;
+pub use self::flowctl::*;
+pub use self::interrupt::*;
+pub use self::link::*;
+pub use self::mtu::*;
+pub use self::offload::*;
+pub use self::phy::*;
+pub use self::regs::*;
+pub use self::rxpool::*;
+pub use self::stats::*;
+pub use self::txclean::*;
+pub use self::zerocopy::*;
+#[cfg(any(test, feature = "sim"))]
+pub use self::sim::*;
@@ -0,0 +1,165 @@
+// MTU and jumbo frames: receive buffer size and long packets [E1000 13.4.22]
+use super::e1000::{E1000Device, KernelFunc};
+use super::e1000_const::*;
+use super::error::E1000Error;
+use super::regs::E1000Regs;
+
+/// MTU of standard Ethernet
+pub const E1000_DEFAULT_MTU: usize = 1500;
+/// Smallest MTU IPv4 allows
+pub const E1000_MIN_MTU: usize = 68;
+/// Largest MTU, the hardware takes frames up to 16128 bytes with the FCS
+pub const E1000_MAX_MTU: usize = 16128 - ETH_HLEN as usize - 4;
+
+/// Size of the mbufs for frames up to `max_frame` bytes, unless `E1000Config` fixes it.
+/// Jumbo frames are split across 4096-byte receive buffers rather than taking
+/// a 16 KiB buffer each.
+pub(super) fn mbuf_size_for(max_frame: usize) -> usize {
+    if max_frame <= 2048 {
+        2048
+    } else {
+        4096
+    }
+}
+
+/// What of the filters and offloads only the registers hold, carried over a reset
+struct HwSettings {
+    ctl: u32,
+    rctl_vfe: u32,
+    rxcsum: u32,
+    ra: [u32; E1000_RAR_ENTRIES * 2],
+    vfta: [u32; E1000_VLAN_FILTER_TBL_SIZE],
+}
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Largest frame sent or received: the MTU plus the Ethernet header and a VLAN tag
+    pub fn max_frame_size(&self) -> usize {
+        self.mtu + (ETH_HLEN + VLAN_HLEN) as usize
+    }
+
+    /// The RCTL bits selecting the receive buffer size, and long packets if
+    /// the MTU is beyond the standard one
+    pub(super) fn rctl_buffer_bits(&self) -> u32 {
+        let size = match self.mbuf_size {
+            256 => E1000_RCTL_SZ_256,
+            512 => E1000_RCTL_SZ_512,
+            1024 => E1000_RCTL_SZ_1024,
+            2048 => E1000_RCTL_SZ_2048,
+            4096 => E1000_RCTL_BSEX | E1000_RCTL_SZ_4096,
+            8192 => E1000_RCTL_BSEX | E1000_RCTL_SZ_8192,
+            16384 => E1000_RCTL_BSEX | E1000_RCTL_SZ_16384,
+            size => {
+                error!("e1000, no receive buffer size of {} bytes", size);
+                E1000_RCTL_SZ_2048
+            }
+        };
+        if self.mtu > E1000_DEFAULT_MTU {
+            size | E1000_RCTL_LPE
+        } else {
+            size
+        }
+    }
+
+    /// The current MTU
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Change the MTU, jumbo frames up to `E1000_MAX_MTU`.
+    /// When the mbufs must grow or shrink, the device is drained and reset, then brought
+    /// up again on new mbufs: packets not yet taken by `e1000_recv` are dropped, and so
+    /// are the ones queued for transmission if the transmitter can't catch up.
+    /// Filters, offloads and the forced link settings are kept.
+    /// With a buffer size set by `E1000Config` the mbufs stay, jumbo frames spanning several.
+    /// Not while an rx pool is attached.
+    pub fn set_mtu(&mut self, mtu: usize) -> Result<(), E1000Error> {
+        if !(E1000_MIN_MTU..=E1000_MAX_MTU).contains(&mtu) {
+            error!("e1000, invalid MTU {}", mtu);
+            return Err(E1000Error::InvalidParam);
+        }
+        if self.rx_pool.is_some() {
+            error!("e1000, detach the rx pool before changing the MTU");
+            return Err(E1000Error::InvalidConfig);
+        }
+
+        let old_mtu = self.mtu;
+        self.mtu = mtu;
+        let size = self.config.mbuf_size.unwrap_or_else(|| mbuf_size_for(self.max_frame_size()));
+        if size != self.mbuf_size {
+            let tx_mbufs = match Self::alloc_mbufs(&mut self.kfn, self.tx_ring.len(), size) {
+                Some(mbufs) => mbufs,
+                None => {
+                    error!("e1000, alloc dma tx buffer failed");
+                    self.mtu = old_mtu;
+                    return Err(E1000Error::AllocFailed);
+                }
+            };
+            let rx_mbufs = match Self::alloc_mbufs(&mut self.kfn, self.rx_ring.len(), size) {
+                Some(mbufs) => mbufs,
+                None => {
+                    error!("e1000, alloc dma rx buffer failed");
+                    Self::free_mbufs(&mut self.kfn, &tx_mbufs.0, size);
+                    self.mtu = old_mtu;
+                    return Err(E1000Error::AllocFailed);
+                }
+            };
+
+            // Nothing DMAed to or from the old mbufs any more, as when dropping the device
+            let settings = self.e1000_save_settings();
+            self.update_stats(); // cleared by the reset
+            if let Err(err) = self.e1000_quiesce() {
+                Self::free_mbufs(&mut self.kfn, &tx_mbufs.0, size);
+                Self::free_mbufs(&mut self.kfn, &rx_mbufs.0, size);
+                self.mtu = old_mtu;
+                return Err(err);
+            }
+
+            Self::free_mbufs(&mut self.kfn, &self.tx_mbufs, self.mbuf_size);
+            Self::free_mbufs(&mut self.kfn, &self.rx_mbufs, self.mbuf_size);
+            self.e1000_use_mbufs(tx_mbufs, rx_mbufs, size);
+
+            self.e1000_init()?;
+            self.e1000_restore_settings(&settings);
+        }
+
+        let rctl = self.regs.read(E1000_RCTL);
+        let rctl = rctl & !(E1000_RCTL_SZ_MASK | E1000_RCTL_BSEX | E1000_RCTL_LPE);
+        self.regs.write(E1000_RCTL, rctl | self.rctl_buffer_bits());
+        self.e1000_write_flush();
+        info!("e1000 MTU: {}, mbuf size: {}", self.mtu, self.mbuf_size);
+        Ok(())
+    }
+
+    fn e1000_save_settings(&self) -> HwSettings {
+        let mut ra = [0; E1000_RAR_ENTRIES * 2];
+        for (i, value) in ra.iter_mut().enumerate() {
+            *value = self.regs.read(E1000_RA + i);
+        }
+        let mut vfta = [0; E1000_VLAN_FILTER_TBL_SIZE];
+        for (i, value) in vfta.iter_mut().enumerate() {
+            *value = self.regs.read(E1000_VFTA + i);
+        }
+        HwSettings {
+            ctl: self.regs.read(E1000_CTL),
+            rctl_vfe: self.regs.read(E1000_RCTL) & E1000_RCTL_VFE,
+            rxcsum: self.regs.read(E1000_RXCSUM),
+            ra,
+            vfta,
+        }
+    }
+
+    /// Put back what `e1000_init` doesn't set up from the state of the driver
+    fn e1000_restore_settings(&mut self, settings: &HwSettings) {
+        self.regs.write(E1000_CTL, settings.ctl & !E1000_CTL_RST);
+        let rctl = self.regs.read(E1000_RCTL);
+        self.regs.write(E1000_RCTL, rctl | settings.rctl_vfe);
+        self.regs.write(E1000_RXCSUM, settings.rxcsum);
+        for (i, &value) in settings.ra.iter().enumerate() {
+            self.regs.write(E1000_RA + i, value);
+        }
+        for (i, &value) in settings.vfta.iter().enumerate() {
+            self.regs.write(E1000_VFTA + i, value);
+        }
+        self.e1000_write_flush();
+    }
+}
@@ -0,0 +1,246 @@
+// Checksum and TCP segmentation offload: TCP/IP context descriptors on transmit
+// [E1000 3.3.5 ~ 3.3.7], RXCSUM on receive [E1000 3.2.9]
+use super::e1000::{E1000Device, KernelFunc, TxContextDesc};
+use super::e1000_const::*;
+use super::error::E1000Error;
+use super::regs::E1000Regs;
+
+/// Size of an IPv4 header without options
+const IPV4_HEADER_LEN: u8 = 20;
+/// Offset of the checksum in the IPv4 header
+const IPV4_CHECKSUM_OFFSET: u8 = 10;
+/// Largest packet handed to the hardware for segmentation, headers included
+pub const E1000_TSO_MAX_SIZE: usize = 64 * 1024;
+
+/// The layer 4 protocol whose checksum the hardware fills in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum L4Protocol {
+    Tcp,
+    Udp,
+}
+
+impl L4Protocol {
+    /// Offset of the checksum in the protocol header
+    fn checksum_offset(self) -> u8 {
+        match self {
+            L4Protocol::Tcp => 16,
+            L4Protocol::Udp => 6,
+        }
+    }
+}
+
+/// Per-packet offload requests for `e1000_transmit_offload`.
+///
+/// The hardware only sums up the bytes, so before handing the packet over
+/// the IPv4 header checksum must be zero and the TCP/UDP checksum must hold
+/// the (not complemented) sum of the pseudo header.
+/// With TSO the pseudo header sum leaves out the TCP length, which the hardware
+/// adds for every segment along with the IPv4 total length.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TxOffload {
+    /// Offset of the IP header in the frame, 14 for an untagged Ethernet frame
+    pub ip_offset: u8,
+    /// Offset of the TCP/UDP header in the frame
+    pub l4_offset: u8,
+    /// Fill in the IPv4 header checksum
+    pub ip_checksum: bool,
+    /// Fill in the TCP or UDP checksum
+    pub l4_checksum: Option<L4Protocol>,
+    /// Insert this 802.1Q tag, needs `set_vlan_offload(true)`
+    pub vlan_tag: Option<u16>,
+    /// Segment the TCP payload into packets of at most this many bytes (TSO),
+    /// needs `l4_checksum: Some(L4Protocol::Tcp)`
+    pub mss: Option<u16>,
+}
+
+impl TxOffload {
+    /// Whether any checksum is requested, so that a context is needed
+    fn needs_context(&self) -> bool {
+        self.ip_checksum || self.l4_checksum.is_some()
+    }
+
+    /// Length of the headers repeated in every TSO segment, up to the end of the TCP header
+    fn tso_header_len(&self, packet: &[u8]) -> usize {
+        self.l4_offset as usize + (packet[self.l4_offset as usize + 12] >> 4) as usize * 4
+    }
+
+    /// The context descriptor describing these offloads
+    fn context(&self, packet: &[u8]) -> TxContextDesc {
+        let mut tucmd = 0;
+        if self.ip_checksum {
+            tucmd |= E1000_TXD_TUCMD_IP;
+        }
+        if self.l4_checksum == Some(L4Protocol::Tcp) {
+            tucmd |= E1000_TXD_TUCMD_TCP;
+        }
+        let (hdr_len, paylen) = match self.mss {
+            Some(_) => {
+                tucmd |= E1000_TXD_CMD_TSE;
+                let hdr_len = self.tso_header_len(packet);
+                (hdr_len, packet.len() - hdr_len)
+            }
+            None => (0, 0),
+        };
+        TxContextDesc {
+            ipcss: self.ip_offset,
+            ipcso: self.ip_offset + IPV4_CHECKSUM_OFFSET,
+            ipcse: self.l4_offset as u16 - 1,
+            tucss: self.l4_offset,
+            tucso: self.l4_offset + self.l4_checksum.map_or(0, |p| p.checksum_offset()),
+            tucse: 0, // to the end of the packet
+            cmd_and_length: ((E1000_TXD_CMD_DEXT | E1000_TXD_CMD_RS | tucmd)
+                << E1000_TXD_CMD_SHIFT)
+                | E1000_TXD_DTYP_C
+                | paylen as u32,
+            status: 0,
+            hdr_len: hdr_len as u8,
+            mss: self.mss.unwrap_or(0),
+        }
+    }
+
+    /// Check the offsets and the segmentation request against a packet
+    fn validate(&self, packet: &[u8]) -> bool {
+        let len = packet.len();
+        if len == 0 {
+            return false;
+        }
+        if !self.needs_context() {
+            return self.mss.is_none();
+        }
+        let ip_offset = self.ip_offset as usize;
+        let l4_offset = self.l4_offset as usize;
+        if l4_offset < ip_offset + IPV4_HEADER_LEN as usize {
+            return false;
+        }
+        // IPCSO and TUCSO are bytes as well, and the checksums lie within the packet
+        let ipcso = ip_offset + IPV4_CHECKSUM_OFFSET as usize;
+        let tucso = l4_offset + self.l4_checksum.map_or(0, |proto| proto.checksum_offset() as usize);
+        if ipcso > u8::MAX as usize || tucso > u8::MAX as usize || tucso + 2 > len {
+            return false;
+        }
+        match self.mss {
+            Some(mss) => {
+                // The checksums are in the headers repeated for every segment
+                let hdr_len = self.tso_header_len(packet);
+                self.l4_checksum == Some(L4Protocol::Tcp)
+                    && mss > 0
+                    && tucso + 2 <= hdr_len
+                    && hdr_len <= u8::MAX as usize
+                    && hdr_len < len
+                    && len <= E1000_TSO_MAX_SIZE
+            }
+            None => true,
+        }
+    }
+}
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Transmitting a network packet, the hardware fills in the checksums, segments
+    /// the TCP payload and inserts the VLAN tag as requested by `offload`.
+    /// A packet larger than a buffer is spread over a chain of data descriptors.
+    /// A context descriptor is only queued when the offsets differ from the previous packet's.
+    pub fn e1000_transmit_offload(
+        &mut self,
+        packet: &[u8],
+        offload: &TxOffload,
+    ) -> Result<usize, E1000Error> {
+        if !offload.validate(packet) {
+            error!("e1000, invalid offload request {:?} for {} bytes", offload, packet.len());
+            return Err(E1000Error::InvalidParam);
+        }
+        if offload.mss.is_none() && packet.len() > self.max_frame_size() {
+            error!("The packet: {} to be send is TOO LARGE", packet.len());
+            return Err(E1000Error::FrameTooLarge);
+        }
+        if let Some(mss) = offload.mss {
+            // Every segment, headers included, has to fit a frame of the current MTU
+            let hdr_len = offload.tso_header_len(packet);
+            if hdr_len + mss as usize > self.max_frame_size() {
+                error!(
+                    "e1000, TSO segment of {} + {} bytes beyond the {} byte frame",
+                    hdr_len,
+                    mss,
+                    self.max_frame_size()
+                );
+                return Err(E1000Error::InvalidParam);
+            }
+        }
+        if offload.vlan_tag.is_some() && !self.vlan_offload() {
+            error!("e1000, VLAN tag insertion is not enabled");
+            return Err(E1000Error::InvalidConfig);
+        }
+
+        let context = if offload.needs_context() {
+            Some(offload.context(packet)).filter(|ctx| self.tx_context != Some(*ctx))
+        } else {
+            None
+        };
+        let ring_len = self.tx_ring.len();
+        let chunks = (packet.len() + self.mbuf_size - 1) / self.mbuf_size;
+        let count = chunks + context.is_some() as usize;
+        if count >= ring_len {
+            error!("e1000, {} bytes need more than {} descriptors", packet.len(), ring_len);
+            return Err(E1000Error::FrameTooLarge);
+        }
+        let mut tindex = self.e1000_tx_reserve(count)?;
+
+        if let Some(ctx) = context {
+            *self.tx_context_desc(tindex) = ctx;
+            self.tx_context = Some(ctx);
+            tindex = (tindex + 1) % ring_len;
+        }
+
+        let mut dcmd =
+            E1000_TXD_CMD_DEXT | E1000_TXD_CMD_RS | E1000_TXD_CMD_IFCS | self.e1000_tx_cmd_ide();
+        if offload.vlan_tag.is_some() {
+            dcmd |= E1000_TXD_CMD_VLE;
+        }
+        if offload.mss.is_some() {
+            dcmd |= E1000_TXD_CMD_TSE;
+        }
+        let mut popts = 0;
+        if offload.ip_checksum {
+            popts |= E1000_TXD_POPTS_IXSM;
+        }
+        if offload.l4_checksum.is_some() {
+            popts |= E1000_TXD_POPTS_TXSM;
+        }
+
+        for (i, chunk) in packet.chunks(self.mbuf_size).enumerate() {
+            let length = self.e1000_tx_copy(tindex, chunk);
+            let eop = if i + 1 == chunks { E1000_TXD_CMD_EOP } else { 0 };
+
+            let addr = self.tx_mbufs_dma[tindex] as u64;
+            let desc = self.tx_data_desc(tindex);
+            desc.addr = addr;
+            desc.cmd_type_len =
+                ((dcmd | eop) << E1000_TXD_CMD_SHIFT) | E1000_TXD_DTYP_D | length as u32;
+            desc.status = 0;
+            desc.popts = popts;
+            desc.special = offload.vlan_tag.unwrap_or(0);
+            tindex = (tindex + 1) % ring_len;
+        }
+
+        self.e1000_tx_kick(tindex, packet.len());
+
+        Ok(packet.len())
+    }
+
+    /// Let the hardware check the IPv4 and TCP/UDP checksums of received packets,
+    /// reported in `RxMeta`. Enabled by `e1000_init`.
+    pub fn set_rx_checksum_offload(&mut self, enable: bool) {
+        let rxcsum = self.regs.read(E1000_RXCSUM);
+        let offloads = E1000_RXCSUM_IPOFLD | E1000_RXCSUM_TUOFLD;
+        if enable {
+            self.regs.write(E1000_RXCSUM, rxcsum | offloads);
+        } else {
+            self.regs.write(E1000_RXCSUM, rxcsum & !offloads);
+        }
+        self.e1000_write_flush();
+    }
+
+    /// Whether received checksums are checked by the hardware
+    pub fn rx_checksum_offload(&self) -> bool {
+        self.regs.read(E1000_RXCSUM) & (E1000_RXCSUM_IPOFLD | E1000_RXCSUM_TUOFLD) != 0
+    }
+}
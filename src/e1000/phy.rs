@@ -0,0 +1,233 @@
+// PHY management through MDIC: register access, auto-negotiation and forced speed/duplex
+// [E1000 8.2.4, 13.4.7, 13.5]
+use super::e1000::{E1000Device, KernelFunc};
+use super::e1000_const::*;
+use super::error::E1000Error;
+use super::link::{Duplex, LinkSpeed};
+use super::regs::E1000Regs;
+use core::ops::{BitAnd, BitOr, BitOrAssign};
+
+/// A set of link modes, advertised by the PHY or by the link partner
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkModes(u32);
+
+impl LinkModes {
+    pub const HALF_10: LinkModes = LinkModes(1 << 0);
+    pub const FULL_10: LinkModes = LinkModes(1 << 1);
+    pub const HALF_100: LinkModes = LinkModes(1 << 2);
+    pub const FULL_100: LinkModes = LinkModes(1 << 3);
+    /// 1000BASE-T half duplex isn't supported, by the 8254x nor by most partners
+    pub const FULL_1000: LinkModes = LinkModes(1 << 4);
+    /// Symmetric PAUSE frames
+    pub const PAUSE: LinkModes = LinkModes(1 << 5);
+    /// Asymmetric PAUSE direction
+    pub const ASYM_PAUSE: LinkModes = LinkModes(1 << 6);
+    /// Every speed and duplex, what the PHY advertises after reset
+    pub const ALL_SPEEDS: LinkModes = LinkModes(0x1f);
+
+    /// No mode at all
+    pub const fn empty() -> Self {
+        LinkModes(0)
+    }
+
+    /// The raw bits
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether no mode is set
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether all the modes of `other` are set
+    pub const fn contains(self, other: LinkModes) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Encode into the autoneg advertisement and the 1000BASE-T control registers
+    pub(super) fn to_phy(self) -> (u16, u16) {
+        let mut adv = NWAY_AR_SELECTOR_802_3;
+        let mut gtctrl = 0;
+        for (mode, bit) in NWAY_BITS {
+            if self.contains(mode) {
+                adv |= bit;
+            }
+        }
+        if self.contains(LinkModes::FULL_1000) {
+            gtctrl |= CR_1000T_FD_CAPS;
+        }
+        (adv, gtctrl)
+    }
+
+    /// Decode an advertisement or link partner ability register, with the
+    /// 1000BASE-T bit found in the other register
+    pub(super) fn from_phy(nway: u16, full_1000: bool) -> Self {
+        let mut modes = LinkModes::empty();
+        for (mode, bit) in NWAY_BITS {
+            if nway & bit != 0 {
+                modes |= mode;
+            }
+        }
+        if full_1000 {
+            modes |= LinkModes::FULL_1000;
+        }
+        modes
+    }
+}
+
+/// Link modes and their bits in the advertisement and link partner ability registers
+const NWAY_BITS: [(LinkModes, u16); 6] = [
+    (LinkModes::HALF_10, NWAY_AR_10T_HD_CAPS),
+    (LinkModes::FULL_10, NWAY_AR_10T_FD_CAPS),
+    (LinkModes::HALF_100, NWAY_AR_100TX_HD_CAPS),
+    (LinkModes::FULL_100, NWAY_AR_100TX_FD_CAPS),
+    (LinkModes::PAUSE, NWAY_AR_PAUSE),
+    (LinkModes::ASYM_PAUSE, NWAY_AR_ASM_DIR),
+];
+
+impl BitOr for LinkModes {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        LinkModes(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for LinkModes {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for LinkModes {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        LinkModes(self.0 & rhs.0)
+    }
+}
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Read a register of the internal PHY
+    pub fn phy_read(&mut self, reg: u32) -> Result<u16, E1000Error> {
+        let mdic = self.e1000_mdic(reg, E1000_MDIC_OP_READ)?;
+        Ok((mdic & E1000_MDIC_DATA_MASK) as u16)
+    }
+
+    /// Write a register of the internal PHY
+    pub fn phy_write(&mut self, reg: u32, data: u16) -> Result<(), E1000Error> {
+        self.e1000_mdic(reg, E1000_MDIC_OP_WRITE | data as u32)?;
+        Ok(())
+    }
+
+    /// Start an MDI access and wait for MDIC.R, failing if the PHY reported MDIC.E
+    fn e1000_mdic(&mut self, reg: u32, op: u32) -> Result<u32, E1000Error> {
+        if reg >= E1000_PHY_REGS {
+            error!("e1000, no PHY register {:#x}", reg);
+            return Err(E1000Error::InvalidParam);
+        }
+        self.regs.write(
+            E1000_MDIC,
+            op | (reg << E1000_MDIC_REG_SHIFT) | (E1000_PHY_ADDRESS << E1000_MDIC_PHY_SHIFT),
+        );
+        for _ in 0..E1000_MDIC_TIMEOUT {
+            let mdic = self.regs.read(E1000_MDIC);
+            if mdic & E1000_MDIC_READY == 0 {
+                continue;
+            }
+            if mdic & E1000_MDIC_ERROR != 0 {
+                error!("e1000, MDI access of PHY register {:#x} failed", reg);
+                return Err(E1000Error::PhyError);
+            }
+            return Ok(mdic);
+        }
+        error!("e1000, MDI access of PHY register {:#x} timed out", reg);
+        Err(E1000Error::PhyTimeout)
+    }
+
+    /// Advertise the speeds and duplexes of `modes` and restart auto-negotiation with them.
+    /// At least one must be in `modes`; the PAUSE abilities follow `set_flow_control`.
+    pub fn phy_advertise(&mut self, modes: LinkModes) -> Result<(), E1000Error> {
+        let modes = modes & LinkModes::ALL_SPEEDS;
+        if modes.is_empty() {
+            error!("e1000, no speed and duplex to advertise");
+            return Err(E1000Error::InvalidParam);
+        }
+        let (adv, gtctrl) = (modes | self.fc.mode.advertise()).to_phy();
+        self.phy_write(PHY_AUTONEG_ADV, adv)?;
+        let old_gtctrl = self.phy_read(PHY_1000T_CTRL)?;
+        self.phy_write(PHY_1000T_CTRL, (old_gtctrl & !CR_1000T_FD_CAPS) | gtctrl)?;
+        self.phy_restart_autoneg()
+    }
+
+    /// The link modes the PHY advertises
+    pub fn phy_advertised(&mut self) -> Result<LinkModes, E1000Error> {
+        let adv = self.phy_read(PHY_AUTONEG_ADV)?;
+        let gtctrl = self.phy_read(PHY_1000T_CTRL)?;
+        Ok(LinkModes::from_phy(adv, gtctrl & CR_1000T_FD_CAPS != 0))
+    }
+
+    /// Go back to auto-negotiation, undoing a forced speed and duplex, and restart it.
+    /// The link status change interrupt tells when the link comes up again.
+    pub fn phy_restart_autoneg(&mut self) -> Result<(), E1000Error> {
+        // The MAC takes speed and duplex from the PHY again
+        let ctl = self.regs.read(E1000_CTL) & !(E1000_CTL_FRCSPD | E1000_CTL_FRCDPLX);
+        self.regs.write(E1000_CTL, ctl | E1000_CTL_ASDE);
+        let phy_ctrl = self.phy_read(PHY_CTRL)?;
+        self.phy_write(PHY_CTRL, phy_ctrl | MII_CR_AUTO_NEG_EN | MII_CR_RESTART_AUTO_NEG)
+    }
+
+    /// Whether auto-negotiation has completed
+    pub fn phy_autoneg_complete(&mut self) -> Result<bool, E1000Error> {
+        Ok(self.phy_read(PHY_STATUS)? & MII_SR_AUTONEG_COMPLETE != 0)
+    }
+
+    /// The link modes the link partner advertised, as of the last auto-negotiation
+    pub fn phy_partner_abilities(&mut self) -> Result<LinkModes, E1000Error> {
+        let lp = self.phy_read(PHY_LP_ABILITY)?;
+        let gtstatus = self.phy_read(PHY_1000T_STATUS)?;
+        Ok(LinkModes::from_phy(lp, gtstatus & SR_1000T_LP_FD_CAPS != 0))
+    }
+
+    /// Turn auto-negotiation off and force the speed and duplex, both in the MAC
+    /// and in the PHY. 1000 Mb/s can't be forced, 1000BASE-T needs auto-negotiation.
+    pub fn force_speed_duplex(&mut self, speed: LinkSpeed, duplex: Duplex) -> Result<(), E1000Error> {
+        let (ctl_speed, phy_speed) = match speed {
+            LinkSpeed::Mbps10 => (E1000_CTL_SPD_10, 0),
+            LinkSpeed::Mbps100 => (E1000_CTL_SPD_100, MII_CR_SPEED_SELECT_LSB),
+            LinkSpeed::Mbps1000 => {
+                error!("e1000, 1000 Mb/s can't be forced");
+                return Err(E1000Error::InvalidParam);
+            }
+        };
+        let (ctl_duplex, phy_duplex) = match duplex {
+            Duplex::Full => (E1000_CTL_FD, MII_CR_FULL_DUPLEX),
+            Duplex::Half => (0, 0),
+        };
+
+        let ctl = self.regs.read(E1000_CTL) & !(E1000_CTL_SPD_SEL | E1000_CTL_FD | E1000_CTL_ASDE);
+        self.regs.write(
+            E1000_CTL,
+            ctl | E1000_CTL_FRCSPD | E1000_CTL_FRCDPLX | E1000_CTL_SLU | ctl_speed | ctl_duplex,
+        );
+        let phy_ctrl = self.phy_read(PHY_CTRL)?
+            & !(MII_CR_AUTO_NEG_EN | MII_CR_SPEED_MASK | MII_CR_FULL_DUPLEX);
+        // The M88 PHY only takes a forced speed and duplex through a reset, as in Linux
+        self.phy_write(PHY_CTRL, phy_ctrl | phy_speed | phy_duplex | MII_CR_RESET)?;
+        self.e1000_phy_reset_wait()?;
+        info!("e1000 forced to {} Mb/s {:?} duplex", speed.mbps(), duplex);
+        Ok(())
+    }
+
+    /// Wait for PHY_CTRL.RESET to clear itself once the PHY reset is done
+    fn e1000_phy_reset_wait(&mut self) -> Result<(), E1000Error> {
+        for _ in 0..E1000_PHY_RESET_TIMEOUT {
+            if self.phy_read(PHY_CTRL)? & MII_CR_RESET == 0 {
+                return Ok(());
+            }
+        }
+        error!("e1000, PHY reset timed out");
+        Err(E1000Error::PhyTimeout)
+    }
+}
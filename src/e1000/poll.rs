@@ -0,0 +1,22 @@
+// Budgeted receive, a bounded amount of work per call as NAPI and schedulers want it
+use super::e1000::{E1000Device, KernelFunc, RxMeta};
+use super::regs::E1000Regs;
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Receive at most `budget` frames, as NAPI counts them, handing each frame
+    /// to `on_frame` together with what the hardware reported about it.
+    /// The frame is only borrowed: straight from its mbuf, or reassembled in a buffer
+    /// reused for the next frames spanning several descriptors.
+    /// Returns whether received packets are left on the ring for the next call.
+    pub fn poll_rx<F>(&mut self, budget: usize, mut on_frame: F) -> bool
+    where
+        F: FnMut(&[u8], RxMeta),
+    {
+        for _ in 0..budget {
+            if !self.e1000_rx_take(&mut on_frame) {
+                return false;
+            }
+        }
+        self.e1000_rx_peek().is_some()
+    }
+}
@@ -0,0 +1,89 @@
+// Register access backends of the e1000 driver
+use super::super::Volatile;
+use core::{mem::size_of, slice::from_raw_parts_mut};
+
+#[cfg(any(test, feature = "sim"))]
+use alloc::{rc::Rc, vec, vec::Vec};
+#[cfg(any(test, feature = "sim"))]
+use core::cell::RefCell;
+#[cfg(any(test, feature = "sim"))]
+use super::e1000_const::{E1000_CTL, E1000_CTL_RST};
+
+/// 0x00000 ~ 0x1FFFF, I/O-Mapped Internal Registers and Memories
+pub const E1000_REGS_SIZE: usize = 0x20000;
+
+/// Access to the e1000 register space.
+/// `reg` is the index of a 32-bit register, i.e. its byte offset divided by 4.
+pub trait E1000Regs {
+    /// Read a 32-bit register
+    fn read(&self, reg: usize) -> u32;
+
+    /// Write a 32-bit register
+    fn write(&mut self, reg: usize, value: u32);
+}
+
+/// The e1000 registers which were mapped into memory (MMIO)
+pub struct MmioRegs {
+    regs: &'static mut [Volatile<u32>],
+}
+
+impl MmioRegs {
+    /// mapped_regs is the memory address at which the e1000's registers are mapped.
+    pub fn new(mapped_regs: usize) -> Self {
+        // 处理网卡寄存器配置: 由一个指针和一个长度len形成一个slice切片。len是元素的个数，而非字节数。
+        let len = E1000_REGS_SIZE / size_of::<u32>();
+        let regs = unsafe { from_raw_parts_mut(mapped_regs as *mut Volatile<u32>, len) };
+        MmioRegs { regs }
+    }
+}
+
+impl E1000Regs for MmioRegs {
+    fn read(&self, reg: usize) -> u32 {
+        self.regs[reg].read()
+    }
+
+    fn write(&mut self, reg: usize, value: u32) {
+        self.regs[reg].write(value);
+    }
+}
+
+/// A plain in-memory register file.
+/// Every register just keeps the last value written to it, which is enough to
+/// check what the driver programs without real hardware.
+/// Only CTRL.RST clears itself, as if the reset finished at once.
+/// Clones share the same registers, so a test can keep one to look at.
+#[cfg(any(test, feature = "sim"))]
+#[derive(Clone)]
+pub struct MemRegs {
+    regs: Rc<RefCell<Vec<u32>>>,
+}
+
+#[cfg(any(test, feature = "sim"))]
+impl MemRegs {
+    /// New a register file with all registers cleared
+    pub fn new() -> Self {
+        let len = E1000_REGS_SIZE / size_of::<u32>();
+        MemRegs {
+            regs: Rc::new(RefCell::new(vec![0; len])),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "sim"))]
+impl Default for MemRegs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(test, feature = "sim"))]
+impl E1000Regs for MemRegs {
+    fn read(&self, reg: usize) -> u32 {
+        self.regs.borrow()[reg]
+    }
+
+    fn write(&mut self, reg: usize, value: u32) {
+        let value = if reg == E1000_CTL { value & !E1000_CTL_RST } else { value };
+        self.regs.borrow_mut()[reg] = value;
+    }
+}
@@ -0,0 +1,267 @@
+// Zero-copy receive: the rx ring is filled from a pool of buffers, and received
+// buffers are lent to the caller until their guard drops
+use super::e1000::{E1000Device, KernelFunc, RxMeta};
+use super::e1000_const::*;
+use super::error::E1000Error;
+use super::regs::E1000Regs;
+use super::super::Ext;
+use crate::utils::*;
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+use core::slice::{from_raw_parts, from_raw_parts_mut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// DMA buffers for zero-copy receive, owned by the caller and shared with the device
+/// by `e1000_attach_rx_pool`. It must hold more buffers than the rx ring has
+/// descriptors, the rest being what can be lent out at the same time.
+pub struct RxPool {
+    vaddr: usize,
+    dma: usize,
+    pages: usize,
+    buf_size: usize,
+    /// Whether each buffer is in the pool, rather than in the ring or lent out
+    free: Vec<AtomicBool>,
+}
+
+impl RxPool {
+    /// Allocate `count` buffers of `buf_size` bytes, all together
+    pub fn new<K: KernelFunc>(kfn: &mut K, count: usize, buf_size: usize) -> Option<Self> {
+        let pages = ((count * buf_size) + (K::PAGE_SIZE - 1)) / K::PAGE_SIZE;
+        let (vaddr, dma) = kfn.dma_alloc_coherent(pages);
+        if vaddr == 0 {
+            error!("e1000, alloc dma rx pool failed");
+            return None;
+        }
+
+        let mut free = Vec::with_capacity(count);
+        for _ in 0..count {
+            free.push(AtomicBool::new(true));
+        }
+        Some(RxPool { vaddr, dma, pages, buf_size, free })
+    }
+
+    /// Free the DMA memory. No buffer may be in a ring or lent out any more.
+    pub fn free<K: KernelFunc>(self, kfn: &mut K) {
+        if self.available() != self.len() {
+            warn!("e1000, rx pool freed with {} buffers in use", self.len() - self.available());
+        }
+        kfn.dma_free_coherent(self.vaddr, self.pages);
+    }
+
+    /// Number of buffers
+    pub fn len(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Whether the pool has no buffers at all
+    pub fn is_empty(&self) -> bool {
+        self.free.is_empty()
+    }
+
+    /// Number of buffers neither in a ring nor lent out
+    pub fn available(&self) -> usize {
+        self.free.iter().filter(|free| free.load(Ordering::Acquire)).count()
+    }
+
+    /// Size of each buffer
+    pub fn buf_size(&self) -> usize {
+        self.buf_size
+    }
+
+    /// Take a free buffer out of the pool
+    pub(super) fn alloc(&self) -> Option<usize> {
+        self.free.iter().position(|free| {
+            free.compare_exchange(true, false, Ordering::AcqRel, Ordering::Relaxed).is_ok()
+        })
+    }
+
+    /// Put buffer `index` back into the pool
+    pub(super) fn recycle(&self, index: usize) {
+        self.free[index].store(true, Ordering::Release);
+    }
+
+    pub(super) fn vaddr(&self, index: usize) -> usize {
+        self.vaddr + index * self.buf_size
+    }
+
+    pub(super) fn dma(&self, index: usize) -> usize {
+        self.dma + index * self.buf_size
+    }
+}
+
+/// A received packet still in its DMA buffer, lent out of an `RxPool`.
+/// The buffer goes back to the pool when this drops.
+pub struct RxBuf<'p> {
+    pool: &'p RxPool,
+    index: usize,
+    len: usize,
+    meta: RxMeta,
+}
+
+impl<'p> RxBuf<'p> {
+    /// What the hardware reported about the packet
+    pub fn meta(&self) -> RxMeta {
+        self.meta
+    }
+}
+
+impl<'p> Deref for RxBuf<'p> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { from_raw_parts(self.pool.vaddr(self.index) as *const u8, self.len) }
+    }
+}
+
+impl<'p> DerefMut for RxBuf<'p> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { from_raw_parts_mut(self.pool.vaddr(self.index) as *mut u8, self.len) }
+    }
+}
+
+impl<'p> Drop for RxBuf<'p> {
+    fn drop(&mut self) {
+        self.pool.recycle(self.index);
+    }
+}
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Fill the rx ring with buffers of `pool` for `e1000_recv_zerocopy`.
+    /// Packets not yet received are dropped. A frame must fit in one buffer,
+    /// so jumbo frames spanning several descriptors aren't supported.
+    pub fn e1000_attach_rx_pool(&mut self, pool: &'a RxPool) -> Result<(), E1000Error> {
+        if self.rx_pool.is_some() {
+            error!("e1000, an rx pool is attached already");
+            return Err(E1000Error::InvalidConfig);
+        }
+        if pool.buf_size() < self.mbuf_size || self.max_frame_size() > self.mbuf_size {
+            error!(
+                "e1000, rx pool buffers of {} bytes can't take frames of {} bytes",
+                pool.buf_size(),
+                self.max_frame_size()
+            );
+            return Err(E1000Error::InvalidConfig);
+        }
+
+        let mut slots = Vec::with_capacity(self.rx_ring.len());
+        for _ in 0..self.rx_ring.len() {
+            match pool.alloc() {
+                Some(index) => slots.push(index),
+                None => {
+                    error!("e1000, rx pool has fewer buffers than the rx ring");
+                    for &index in slots.iter() {
+                        pool.recycle(index);
+                    }
+                    return Err(E1000Error::InvalidConfig);
+                }
+            }
+        }
+
+        self.rx_pool = Some(pool);
+        self.rx_pool_slots = slots;
+        self.e1000_rx_refill();
+        Ok(())
+    }
+
+    /// Give the ring's buffers back to the attached pool and use the driver's own again.
+    /// Packets not yet received are dropped; buffers lent out stay valid.
+    pub fn e1000_detach_rx_pool(&mut self) {
+        if let Some(pool) = self.rx_pool.take() {
+            for &index in self.rx_pool_slots.iter() {
+                pool.recycle(index);
+            }
+            self.rx_pool_slots.clear();
+            self.e1000_rx_refill();
+        }
+    }
+
+    /// Virtual address of the buffer of rx descriptor `index`
+    pub(super) fn rx_buf_vaddr(&self, index: usize) -> usize {
+        match self.rx_pool {
+            Some(pool) => pool.vaddr(self.rx_pool_slots[index]),
+            None => self.rx_mbufs[index],
+        }
+    }
+
+    /// DMA address of the buffer of rx descriptor `index`
+    fn rx_buf_dma(&self, index: usize) -> usize {
+        match self.rx_pool {
+            Some(pool) => pool.dma(self.rx_pool_slots[index]),
+            None => self.rx_mbufs_dma[index],
+        }
+    }
+
+    /// Point every rx descriptor at its buffer again, with the receiver stopped
+    fn e1000_rx_refill(&mut self) {
+        let rctl = self.regs.read(E1000_RCTL);
+        self.regs.write(E1000_RCTL, rctl & !E1000_RCTL_EN);
+        self.e1000_write_flush();
+
+        for index in 0..self.rx_ring.len() {
+            self.rx_ring[index].addr = self.rx_buf_dma(index) as u64;
+            self.rx_ring[index].status = 0;
+        }
+        fence_w();
+
+        self.regs.write(E1000_RDH, 0);
+        self.regs.write(E1000_RDT, (self.rx_ring.len() - 1) as u32);
+        self.regs.write(E1000_RCTL, rctl);
+        self.e1000_write_flush();
+    }
+
+    /// Take the packet at RDT + 1 off the ring without copying it, if the hardware is
+    /// done with it: its buffer is lent out and the descriptor gets a fresh one from the pool.
+    /// Returns None as well while the pool has no buffer left.
+    pub fn e1000_recv_zerocopy(&mut self) -> Option<RxBuf<'a>> {
+        let pool = match self.rx_pool {
+            Some(pool) => pool,
+            None => {
+                error!("e1000, no rx pool is attached");
+                return None;
+            }
+        };
+        let ring_len = self.rx_ring.len();
+
+        loop {
+            let rindex = (self.regs.read(E1000_RDT) as usize + 1) % ring_len;
+            let status = self.rx_ring[rindex].status as u32;
+            if status & E1000_RXD_STAT_DD == 0 {
+                return None;
+            }
+            if status & E1000_RXD_STAT_EOP == 0 {
+                // Can't happen with frames fitting in a buffer
+                warn!("e1000, drop a frame spanning several rx descriptors");
+                self.e1000_rx_take(|_, _| {});
+                continue;
+            }
+            fence();
+
+            let fresh = match pool.alloc() {
+                Some(index) => index,
+                None => {
+                    warn!("e1000, rx pool exhausted");
+                    return None;
+                }
+            };
+            let desc = &mut self.rx_ring[rindex];
+            let buf = RxBuf {
+                pool,
+                index: self.rx_pool_slots[rindex],
+                len: desc.length as usize,
+                meta: RxMeta::from_desc(desc),
+            };
+            info!("RX PKT {} <<<<<<<<< (zero-copy)", buf.len);
+
+            self.rx_pool_slots[rindex] = fresh;
+            desc.addr = pool.dma(fresh) as u64;
+            desc.status = 0;
+            fence_w();
+            self.regs.write(E1000_RDT, rindex as u32);
+            self.e1000_write_flush();
+
+            self.itr.rx_packets += 1;
+            self.itr.rx_bytes += buf.len;
+            return Some(buf);
+        }
+    }
+}
@@ -0,0 +1,794 @@
+// Software behavioral model of the Intel 82540EM, for host-side testing.
+// It implements the ring, descriptor write-back and interrupt semantics the
+// driver relies on, against DMA memory handed out by `SimKernelFunc`.
+use super::e1000::KernelFunc;
+use super::e1000_const::*;
+use super::phy::LinkModes;
+use super::regs::{E1000Regs, E1000_REGS_SIZE};
+use alloc::alloc::{alloc_zeroed, dealloc, Layout};
+use alloc::collections::VecDeque;
+use alloc::{rc::Rc, vec, vec::Vec};
+use core::cell::RefCell;
+use core::convert::TryInto;
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+
+/// Size of a legacy/extended descriptor in bytes
+const DESC_SIZE: usize = 16;
+/// Largest frame accepted without RCTL.LPE (VLAN tagged, no CRC)
+const MAX_NORMAL_FRAME: usize = 1522;
+/// Low registers of the 64-bit statistics, which don't clear on read
+const STATS_LOW: [usize; 4] = [E1000_GORCL, E1000_GOTCL, E1000_TORL, E1000_TOTL];
+/// Link modes in the order auto-negotiation prefers them, and the STATUS bits they resolve to
+const SIM_LINK_PRIORITY: [(LinkModes, u32); 5] = [
+    (LinkModes::FULL_1000, E1000_STATUS_SPEED_1000 | E1000_STATUS_FD),
+    (LinkModes::FULL_100, E1000_STATUS_SPEED_100 | E1000_STATUS_FD),
+    (LinkModes::HALF_100, E1000_STATUS_SPEED_100),
+    (LinkModes::FULL_10, E1000_STATUS_SPEED_10 | E1000_STATUS_FD),
+    (LinkModes::HALF_10, E1000_STATUS_SPEED_10),
+];
+/// Words of the EEPROM covered by the checksum
+const EEPROM_WORDS: usize = 64;
+/// qemu's default MAC address
+pub const SIM_MAC_ADDRESS: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+/// The simulated bus address of a DMA buffer is its host address with the top bit flipped,
+/// so a driver handing out virtual addresses to the device is caught.
+const SIM_DMA_FLIP: usize = 1 << (usize::BITS - 1);
+
+/// Translate a simulated DMA (bus) address into a host pointer
+pub fn sim_dma_to_virt(dma: usize) -> usize {
+    dma ^ SIM_DMA_FLIP
+}
+
+/// Translate a host pointer into the simulated DMA (bus) address
+pub fn sim_virt_to_dma(vaddr: usize) -> usize {
+    vaddr ^ SIM_DMA_FLIP
+}
+
+/// DMA memory from the host heap for the simulated device
+#[derive(Default)]
+pub struct SimKernelFunc;
+
+impl KernelFunc for SimKernelFunc {
+    fn dma_alloc_coherent(&mut self, pages: usize) -> (usize, usize) {
+        let layout = Layout::from_size_align(pages * Self::PAGE_SIZE, Self::PAGE_SIZE).unwrap();
+        let vaddr = unsafe { alloc_zeroed(layout) } as usize;
+        if vaddr == 0 {
+            return (0, 0);
+        }
+        (vaddr, sim_virt_to_dma(vaddr))
+    }
+
+    fn dma_free_coherent(&mut self, vaddr: usize, pages: usize) {
+        let layout = Layout::from_size_align(pages * Self::PAGE_SIZE, Self::PAGE_SIZE).unwrap();
+        unsafe { dealloc(vaddr as *mut u8, layout) };
+    }
+}
+
+fn dma_read<T>(dma: usize) -> T {
+    unsafe { read_volatile(sim_dma_to_virt(dma) as *const T) }
+}
+
+fn dma_write<T>(dma: usize, value: T) {
+    unsafe { write_volatile(sim_dma_to_virt(dma) as *mut T, value) }
+}
+
+fn dma_read_bytes(dma: usize, buf: &mut [u8]) {
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = dma_read(dma + i);
+    }
+}
+
+fn dma_write_bytes(dma: usize, buf: &[u8]) {
+    for (i, b) in buf.iter().enumerate() {
+        dma_write(dma + i, *b);
+    }
+}
+
+/// Internet checksum of `data[start..=end]`, or up to the end of `data` if `end` is 0,
+/// starting from the partial sum `init`
+fn checksum(data: &[u8], start: usize, end: usize, init: u32) -> u16 {
+    let end = if end == 0 { data.len() } else { data.len().min(end + 1) };
+    let mut sum = init;
+    for word in data[start.min(end)..end].chunks(2) {
+        sum += u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Offsets and segmentation parameters loaded by the last TCP/IP context descriptor
+#[derive(Clone, Copy, Default)]
+struct SimTxContext {
+    ipcss: usize,
+    ipcso: usize,
+    ipcse: usize,
+    tucss: usize,
+    tucso: usize,
+    tucse: usize,
+    tucmd: u32,
+    hdr_len: usize,
+    mss: usize,
+}
+
+impl SimTxContext {
+    fn load(desc: usize) -> Self {
+        SimTxContext {
+            ipcss: dma_read::<u8>(desc) as usize,
+            ipcso: dma_read::<u8>(desc + 1) as usize,
+            ipcse: dma_read::<u16>(desc + 2) as usize,
+            tucss: dma_read::<u8>(desc + 4) as usize,
+            tucso: dma_read::<u8>(desc + 5) as usize,
+            tucse: dma_read::<u16>(desc + 6) as usize,
+            tucmd: dma_read::<u8>(desc + 11) as u32,
+            hdr_len: dma_read::<u8>(desc + 13) as usize,
+            mss: dma_read::<u16>(desc + 14) as usize,
+        }
+    }
+
+    /// Fill in the checksums requested by the packet options,
+    /// `l4_len` is added to the TCP/UDP sum for TSO
+    fn insert_checksums(&self, frame: &mut [u8], popts: u8, l4_len: u32) {
+        let mut insert = |start, end, offset: usize, init| {
+            if offset + 2 <= frame.len() {
+                let sum = checksum(frame, start, end, init);
+                frame[offset..offset + 2].copy_from_slice(&sum.to_be_bytes());
+            }
+        };
+        if popts & E1000_TXD_POPTS_IXSM != 0 {
+            insert(self.ipcss, self.ipcse, self.ipcso, 0);
+        }
+        if popts & E1000_TXD_POPTS_TXSM != 0 {
+            insert(self.tucss, self.tucse, self.tucso, l4_len);
+        }
+    }
+
+    /// Split a TSO packet into segments of `mss` payload bytes, updating the
+    /// IPv4 total length and identification, the TCP sequence number and flags
+    fn segment(&self, frame: &[u8], popts: u8) -> Vec<Vec<u8>> {
+        let (header, payload) = frame.split_at(self.hdr_len.min(frame.len()));
+        let chunks: Vec<&[u8]> = payload.chunks(self.mss.max(1)).collect();
+        let ip_id = u16::from_be_bytes([header[self.ipcss + 4], header[self.ipcss + 5]]);
+        let seq = u32::from_be_bytes(header[self.tucss + 4..self.tucss + 8].try_into().unwrap());
+
+        let mut segments = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut seg = [header, chunk].concat();
+            if self.tucmd & E1000_TXD_TUCMD_IP != 0 {
+                let total_len = (seg.len() - self.ipcss) as u16;
+                seg[self.ipcss + 2..self.ipcss + 4].copy_from_slice(&total_len.to_be_bytes());
+                let id = ip_id.wrapping_add(i as u16);
+                seg[self.ipcss + 4..self.ipcss + 6].copy_from_slice(&id.to_be_bytes());
+            }
+            let seq = seq.wrapping_add((i * self.mss) as u32);
+            seg[self.tucss + 4..self.tucss + 8].copy_from_slice(&seq.to_be_bytes());
+            if i + 1 != chunks.len() {
+                // FIN and PSH only go with the last segment
+                seg[self.tucss + 13] &= !0x09;
+            }
+            let l4_len = (seg.len() - self.tucss) as u32;
+            self.insert_checksums(&mut seg, popts, l4_len);
+            segments.push(seg);
+        }
+        segments
+    }
+}
+
+struct SimState {
+    regs: Vec<u32>,
+    eeprom: [u16; EEPROM_WORDS],
+    /// Data of the packet whose descriptors have been fetched so far
+    tx_pending: Vec<u8>,
+    /// Packet options of the first data descriptor of the pending packet
+    tx_popts: u8,
+    /// Whether the pending packet is to be segmented
+    tx_tse: bool,
+    tx_context: SimTxContext,
+    /// Frames put on the wire
+    tx_frames: VecDeque<Vec<u8>>,
+    /// STATUS bits of the link to the partner, surviving resets
+    link_status: u32,
+    /// Registers of the PHY, which a reset of the MAC leaves alone
+    phy: [u16; E1000_PHY_REGS as usize],
+    /// Whether a link partner is plugged in
+    plugged: bool,
+    /// Modes the link partner advertises
+    partner: LinkModes,
+}
+
+/// A simulated 82540EM.
+/// Use it as the register backend of an `E1000Device`; clones share the same device,
+/// so a test keeps one to play the link partner.
+#[derive(Clone)]
+pub struct SimE1000 {
+    state: Rc<RefCell<SimState>>,
+}
+
+impl SimState {
+    fn reset(&mut self) {
+        self.regs.fill(0);
+        self.regs[E1000_STAT] = self.link_status;
+        self.regs[E1000_VET] = ETH_P_8021Q as u32;
+        // 48 KB of the packet buffer for receiving
+        self.regs[E1000_PBA] = 0x30;
+        self.tx_pending.clear();
+        self.tx_context = SimTxContext::default();
+    }
+
+    /// Start an EEPROM read through EERD; the simulated EEPROM answers at once
+    fn eeprom_read(&mut self, eerd: u32) {
+        if eerd & E1000_EEPROM_RW_REG_START == 0 {
+            return;
+        }
+        let offset = ((eerd >> E1000_EEPROM_RW_ADDR_SHIFT) & 0xff) as usize;
+        let data = self.eeprom.get(offset).copied().unwrap_or(0xffff);
+        self.regs[E1000_EERD] = ((data as u32) << E1000_EEPROM_RW_REG_DATA)
+            | ((offset as u32) << E1000_EEPROM_RW_ADDR_SHIFT)
+            | E1000_EEPROM_RW_REG_DONE;
+    }
+
+    /// An MDI access through MDIC, done at once
+    fn mdic(&mut self, mdic: u32) {
+        let reg = (mdic >> E1000_MDIC_REG_SHIFT) & 0x1f;
+        let phy = (mdic >> E1000_MDIC_PHY_SHIFT) & 0x1f;
+        let data = (mdic & E1000_MDIC_DATA_MASK) as u16;
+        let mut mdic = mdic | E1000_MDIC_READY;
+        if phy != E1000_PHY_ADDRESS {
+            mdic |= E1000_MDIC_ERROR;
+        } else if mdic & E1000_MDIC_OP_READ != 0 {
+            mdic = (mdic & !E1000_MDIC_DATA_MASK) | self.phy[reg as usize] as u32;
+        } else if mdic & E1000_MDIC_OP_WRITE != 0 {
+            self.phy_write(reg, data);
+        }
+        self.regs[E1000_MDIC] = mdic;
+    }
+
+    fn phy_write(&mut self, reg: u32, data: u16) {
+        match reg {
+            PHY_CTRL => {
+                // The reset is done at once, clearing its bit
+                self.phy[PHY_CTRL as usize] = data & !(MII_CR_RESTART_AUTO_NEG | MII_CR_RESET);
+                if data & MII_CR_AUTO_NEG_EN == 0 {
+                    // Like the M88, a forced speed and duplex waits for a reset
+                    if data & MII_CR_RESET != 0 {
+                        self.phy_force();
+                    }
+                } else if data & (MII_CR_RESTART_AUTO_NEG | MII_CR_RESET) != 0 {
+                    self.phy_autoneg();
+                }
+            }
+            // Read only
+            PHY_STATUS | PHY_LP_ABILITY | PHY_1000T_STATUS => {}
+            _ => self.phy[reg as usize] = data,
+        }
+    }
+
+    /// Negotiate the best mode both ends advertise
+    fn phy_autoneg(&mut self) {
+        let (lp, gtctrl) = self.partner.to_phy();
+        self.phy[PHY_LP_ABILITY as usize] = lp;
+        self.phy[PHY_1000T_STATUS as usize] =
+            if gtctrl & CR_1000T_FD_CAPS != 0 { SR_1000T_LP_FD_CAPS } else { 0 };
+        let adv = self.phy[PHY_AUTONEG_ADV as usize];
+        let gtctrl = self.phy[PHY_1000T_CTRL as usize];
+        let ours = LinkModes::from_phy(adv, gtctrl & CR_1000T_FD_CAPS != 0);
+        let common = ours & self.partner;
+        let status = SIM_LINK_PRIORITY
+            .iter()
+            .find(|(mode, _)| common.contains(*mode))
+            .map(|&(_, status)| status);
+        self.phy[PHY_STATUS as usize] |= MII_SR_AUTONEG_COMPLETE;
+        match status {
+            Some(status) if self.plugged => self.link_change(E1000_STATUS_LU | status),
+            _ => self.link_change(0),
+        }
+    }
+
+    /// Take the speed and duplex forced in the PHY control register
+    fn phy_force(&mut self) {
+        let ctrl = self.phy[PHY_CTRL as usize];
+        let speed = match ctrl & MII_CR_SPEED_MASK {
+            MII_CR_SPEED_SELECT_LSB => E1000_STATUS_SPEED_100,
+            MII_CR_SPEED_SELECT_MSB => E1000_STATUS_SPEED_1000,
+            _ => E1000_STATUS_SPEED_10,
+        };
+        let duplex = if ctrl & MII_CR_FULL_DUPLEX != 0 { E1000_STATUS_FD } else { 0 };
+        self.phy[PHY_STATUS as usize] &= !MII_SR_AUTONEG_COMPLETE;
+        if self.plugged {
+            self.link_change(E1000_STATUS_LU | speed | duplex);
+        } else {
+            self.link_change(0);
+        }
+    }
+
+    /// The link goes to `status`, raising a link status change
+    fn link_change(&mut self, status: u32) {
+        self.link_status = status;
+        self.regs[E1000_STAT] = status;
+        if status & E1000_STATUS_LU != 0 {
+            self.phy[PHY_STATUS as usize] |= MII_SR_LINK_STATUS;
+        } else {
+            self.phy[PHY_STATUS as usize] &= !MII_SR_LINK_STATUS;
+        }
+        self.raise(E1000_ICR_LSC);
+    }
+
+    /// Count into a statistics register, saturating like the hardware
+    fn count(&mut self, reg: usize, n: u32) {
+        self.regs[reg] = self.regs[reg].saturating_add(n);
+    }
+
+    /// Count octets into a 64-bit statistics register pair
+    fn count64(&mut self, low: usize, n: usize) {
+        let value = (self.regs[low] as u64 | (self.regs[low + 1] as u64) << 32) + n as u64;
+        self.regs[low] = value as u32;
+        self.regs[low + 1] = (value >> 32) as u32;
+    }
+
+    /// Count a frame of `len` bytes, FCS included, into the good packet counters
+    fn count_good(&mut self, frame: &[u8], len: usize, rx: bool) {
+        let (packets, octets, bcast, mcast) = if rx {
+            (E1000_GPRC, E1000_GORCL, E1000_BPRC, E1000_MPRC)
+        } else {
+            (E1000_GPTC, E1000_GOTCL, E1000_BPTC, E1000_MPTC)
+        };
+        self.count(packets, 1);
+        self.count64(octets, len);
+        if frame[..6] == [0xff; 6] {
+            self.count(bcast, 1);
+        } else if frame[0] & 0x01 != 0 {
+            self.count(mcast, 1);
+        }
+    }
+
+    fn raise(&mut self, cause: u32) {
+        self.regs[E1000_ICR] |= cause;
+    }
+
+    fn ring_base(&self, bal: usize) -> usize {
+        self.regs[bal] as usize | (self.regs[bal + 1] as usize) << 32
+    }
+
+    fn ring_len(&self, len: usize) -> usize {
+        self.regs[len] as usize / DESC_SIZE
+    }
+
+    /// Fetch and process the descriptors between TDH and TDT
+    fn transmit(&mut self) {
+        if self.regs[E1000_TCTL] & E1000_TCTL_EN == 0 {
+            return;
+        }
+        let count = self.ring_len(E1000_TDLEN);
+        let base = self.ring_base(E1000_TDBAL);
+        if count == 0 {
+            return;
+        }
+
+        let mut written_back = false;
+        while self.regs[E1000_TDH] != self.regs[E1000_TDT] {
+            let head = self.regs[E1000_TDH] as usize % count;
+            let desc = base + head * DESC_SIZE;
+            let cmd = dma_read::<u8>(desc + 11) as u32;
+            let extended = cmd & E1000_TXD_CMD_DEXT != 0;
+            let type_len = dma_read::<u32>(desc + 8);
+
+            if extended && type_len & E1000_TXD_DTYP_MASK == E1000_TXD_DTYP_C {
+                self.tx_context = SimTxContext::load(desc);
+            } else {
+                let addr = dma_read::<u64>(desc) as usize;
+                let length = if extended {
+                    (type_len & E1000_TXD_LEN_MASK) as usize
+                } else {
+                    type_len as u16 as usize
+                };
+                if self.tx_pending.is_empty() {
+                    self.tx_popts = if extended { dma_read::<u8>(desc + 13) } else { 0 };
+                    self.tx_tse = extended && cmd & E1000_TXD_CMD_TSE != 0;
+                }
+
+                let start = self.tx_pending.len();
+                self.tx_pending.resize(start + length, 0);
+                dma_read_bytes(addr, &mut self.tx_pending[start..]);
+
+                if cmd & E1000_TXD_CMD_EOP != 0 {
+                    let mut frame = core::mem::take(&mut self.tx_pending);
+                    let frames = if self.tx_tse {
+                        self.tx_context.segment(&frame, self.tx_popts)
+                    } else {
+                        self.tx_context.insert_checksums(&mut frame, self.tx_popts, 0);
+                        vec![frame]
+                    };
+                    for mut frame in frames {
+                        if cmd & E1000_TXD_CMD_VLE != 0 && self.regs[E1000_CTL] & E1000_CTL_VME != 0
+                        {
+                            // Insert the 802.1Q tag after the source address
+                            let special = dma_read::<u16>(desc + 14);
+                            let vet = self.regs[E1000_VET] as u16;
+                            let tag = [vet.to_be_bytes(), special.to_be_bytes()].concat();
+                            frame.splice(12..12, tag);
+                        }
+                        self.count(E1000_TPT, 1);
+                        self.count64(E1000_TOTL, frame.len() + 4);
+                        self.count_good(&frame, frame.len() + 4, false);
+                        self.tx_frames.push_back(frame);
+                    }
+                }
+            }
+            if cmd & E1000_TXD_CMD_RS != 0 {
+                let status = dma_read::<u8>(desc + 12);
+                dma_write(desc + 12, status | E1000_TXD_STAT_DD as u8);
+                written_back = true;
+            }
+            self.regs[E1000_TDH] = ((head + 1) % count) as u32;
+        }
+
+        if written_back {
+            self.raise(E1000_ICR_TXDW);
+        }
+        self.raise(E1000_ICR_TXQE);
+    }
+
+    /// Receive buffer size selected by RCTL.BSIZE and RCTL.BSEX
+    fn rx_buffer_size(&self) -> usize {
+        let rctl = self.regs[E1000_RCTL];
+        let bsize = (rctl >> 16) & 0b11;
+        if rctl & E1000_RCTL_BSEX == 0 {
+            2048 >> bsize
+        } else {
+            [2048, 16384, 8192, 4096][bsize as usize]
+        }
+    }
+
+    /// Exact match against the valid Receive Address entries
+    fn rar_match(&self, frame: &[u8]) -> bool {
+        (0..E1000_RAR_ENTRIES).any(|i| {
+            let ral = self.regs[E1000_RA + i * 2];
+            let rah = self.regs[E1000_RA + i * 2 + 1];
+            if rah & E1000_RAH_AV == 0 {
+                return false;
+            }
+            let low = ral.to_le_bytes();
+            let high = (rah as u16).to_le_bytes();
+            let mac = [low[0], low[1], low[2], low[3], high[0], high[1]];
+            match (rah & E1000_RAH_AS_MASK) >> E1000_RAH_AS_SHIFT {
+                0b00 => frame[..6] == mac,
+                0b01 => frame[6..12] == mac,
+                _ => false,
+            }
+        })
+    }
+
+    /// Inexact match of a multicast destination against the Multicast Table Array
+    fn mta_match(&self, frame: &[u8]) -> bool {
+        let (low, high) = (frame[4] as u32, frame[5] as u32);
+        let hash = match (self.regs[E1000_RCTL] & E1000_RCTL_MO_MASK) >> E1000_RCTL_MO_SHIFT {
+            0 => (low >> 4) | (high << 4),
+            1 => (low >> 3) | (high << 5),
+            2 => (low >> 2) | (high << 6),
+            _ => low | (high << 8),
+        } & 0xFFF;
+        self.regs[E1000_MTA + (hash >> 5) as usize] & (1 << (hash & 0x1F)) != 0
+    }
+
+    /// The tag control information of an 802.1Q tagged frame
+    fn vlan_tag(&self, frame: &[u8]) -> Option<u16> {
+        let vet = self.regs[E1000_VET] as u16;
+        if frame.len() >= 18 && u16::from_be_bytes([frame[12], frame[13]]) == vet {
+            Some(u16::from_be_bytes([frame[14], frame[15]]))
+        } else {
+            None
+        }
+    }
+
+    fn vfta_match(&self, vid: u16) -> bool {
+        self.regs[E1000_VFTA + (vid as usize >> 5)] & (1 << (vid & 0x1F)) != 0
+    }
+
+    /// Receive checksum offload [E1000 3.2.9]: the status and error bits of the
+    /// IPv4 and TCP/UDP checksums, and the packet checksum from RXCSUM.PCSS on
+    fn rx_checksum(&self, frame: &[u8]) -> (u32, u32, u16) {
+        let rxcsum = self.regs[E1000_RXCSUM];
+        let pcss = (rxcsum & E1000_RXCSUM_PCSS_MASK) as usize;
+        let csum = !checksum(frame, pcss, 0, 0);
+        if rxcsum & (E1000_RXCSUM_IPOFLD | E1000_RXCSUM_TUOFLD) == 0 {
+            return (E1000_RXD_STAT_IXSM, 0, csum);
+        }
+
+        let ip = ETH_HLEN as usize;
+        if frame.len() < ip + 20 || frame[12..14] != [0x08, 0x00] || frame[ip] >> 4 != 4 {
+            return (0, 0, csum);
+        }
+        let ihl = (frame[ip] & 0x0F) as usize * 4;
+        let end = frame.len().min(ip + u16::from_be_bytes([frame[ip + 2], frame[ip + 3]]) as usize);
+        if ihl < 20 || ip + ihl > end {
+            return (0, 0, csum);
+        }
+
+        let (mut status, mut errors) = (0, 0);
+        if rxcsum & E1000_RXCSUM_IPOFLD != 0 {
+            status |= E1000_RXD_STAT_IPCS;
+            if checksum(frame, ip, ip + ihl - 1, 0) != 0 {
+                errors |= E1000_RXD_ERR_IPE;
+            }
+        }
+
+        let proto = frame[ip + 9];
+        let fragment = u16::from_be_bytes([frame[ip + 6], frame[ip + 7]]) & 0x3FFF != 0;
+        let l4 = ip + ihl;
+        let l4_len = end - l4;
+        let csum_offset = match proto {
+            6 => 16,
+            17 => 6,
+            _ => return (status, errors, csum),
+        };
+        if rxcsum & E1000_RXCSUM_TUOFLD != 0 && !fragment && l4 + csum_offset + 2 <= end {
+            // UDP without checksum
+            if proto == 17 && frame[l4 + 6..l4 + 8] == [0, 0] {
+                return (status, errors, csum);
+            }
+            let pseudo = frame[ip + 12..ip + 20]
+                .chunks(2)
+                .map(|w| u16::from_be_bytes([w[0], w[1]]) as u32)
+                .sum::<u32>()
+                + proto as u32
+                + l4_len as u32;
+            status |= E1000_RXD_STAT_TCPCS;
+            if checksum(&frame[..end], l4, 0, pseudo) != 0 {
+                errors |= E1000_RXD_ERR_TCPE;
+            }
+        }
+        (status, errors, csum)
+    }
+
+    /// Receive address filtering [E1000 13.5.1]
+    fn address_match(&self, frame: &[u8]) -> bool {
+        if frame.len() < 14 {
+            return false;
+        }
+        let rctl = self.regs[E1000_RCTL];
+        if self.rar_match(frame) {
+            return true;
+        }
+        if frame[..6] == [0xff; 6] {
+            return rctl & (E1000_RCTL_BAM | E1000_RCTL_MPE) != 0;
+        }
+        if frame[0] & 0x01 != 0 {
+            rctl & E1000_RCTL_MPE != 0 || self.mta_match(frame)
+        } else {
+            rctl & E1000_RCTL_UPE != 0
+        }
+    }
+
+    /// DMA a frame into the receive ring, spanning several descriptors if needed
+    fn receive(&mut self, frame: &[u8]) -> bool {
+        let rctl = self.regs[E1000_RCTL];
+        if rctl & E1000_RCTL_EN == 0 || self.regs[E1000_STAT] & E1000_STATUS_LU == 0 {
+            return false;
+        }
+        // Octets on the wire, with the FCS
+        let wire_len = frame.len() + 4;
+        self.count(E1000_TPR, 1);
+        self.count64(E1000_TORL, wire_len);
+        if !self.address_match(frame) {
+            return false;
+        }
+        let dst = &frame[..6];
+
+        // 802.1Q: filter on the VLAN ID, and strip the tag into the descriptor
+        let mut stripped = Vec::new();
+        let mut special = 0;
+        let mut vp = 0;
+        let frame = match self.vlan_tag(frame) {
+            Some(tci) => {
+                if rctl & E1000_RCTL_VFE != 0 && !self.vfta_match(tci & VLAN_VID_MASK) {
+                    return false;
+                }
+                if self.regs[E1000_CTL] & E1000_CTL_VME != 0 {
+                    stripped.extend_from_slice(&frame[..12]);
+                    stripped.extend_from_slice(&frame[16..]);
+                    special = tci;
+                    vp = E1000_RXD_STAT_VP;
+                    &stripped[..]
+                } else {
+                    frame
+                }
+            }
+            None => frame,
+        };
+        if frame.len() > MAX_NORMAL_FRAME && rctl & E1000_RCTL_LPE == 0 {
+            self.count(E1000_ROC, 1);
+            return false;
+        }
+        let count = self.ring_len(E1000_RDLEN);
+        let base = self.ring_base(E1000_RDBAL);
+        if count == 0 {
+            return false;
+        }
+
+        // The hardware owns the descriptors from RDH up to, not including, RDT
+        let head = self.regs[E1000_RDH] as usize % count;
+        let tail = self.regs[E1000_RDT] as usize % count;
+        let free = (tail + count - head) % count;
+        let bufsize = self.rx_buffer_size();
+        let needed = (frame.len() + bufsize - 1) / bufsize;
+        if needed > free {
+            self.count(E1000_RNBC, 1);
+            self.count(E1000_MPC, 1);
+            self.raise(E1000_ICR_RXO);
+            return false;
+        }
+
+        let (csum_status, errors, csum) = self.rx_checksum(frame);
+        let mut index = head;
+        let mut chunks = frame.chunks(bufsize).peekable();
+        while let Some(chunk) = chunks.next() {
+            let desc = base + index * DESC_SIZE;
+            let addr = dma_read::<u64>(desc) as usize;
+            dma_write_bytes(addr, chunk);
+            let mut status = E1000_RXD_STAT_DD;
+            let mut last = (0, 0);
+            if chunks.peek().is_none() {
+                status |= E1000_RXD_STAT_EOP | vp | csum_status;
+                last = (csum, errors);
+            }
+            dma_write(desc + 8, chunk.len() as u16);
+            dma_write(desc + 10, last.0);
+            dma_write(desc + 13, last.1 as u8);
+            dma_write(desc + 14, special);
+            dma_write(desc + 12, status as u8);
+            index = (index + 1) % count;
+        }
+        self.regs[E1000_RDH] = index as u32;
+        self.count_good(dst, wire_len, true);
+        self.raise(E1000_ICR_RXT0);
+        true
+    }
+}
+
+impl SimE1000 {
+    /// New a simulated device, in its power-on state
+    pub fn new() -> Self {
+        let mut state = SimState {
+            regs: vec![0; E1000_REGS_SIZE / size_of::<u32>()],
+            eeprom: [0; EEPROM_WORDS],
+            tx_pending: Vec::new(),
+            tx_popts: 0,
+            tx_tse: false,
+            tx_context: SimTxContext::default(),
+            tx_frames: VecDeque::new(),
+            link_status: E1000_STATUS_LU | E1000_STATUS_FD | E1000_STATUS_SPEED_1000,
+            phy: [0; E1000_PHY_REGS as usize],
+            plugged: true,
+            partner: LinkModes::ALL_SPEEDS,
+        };
+        // Auto-negotiated 1000 Mb/s full duplex with a partner of every speed
+        state.phy[PHY_CTRL as usize] = MII_CR_AUTO_NEG_EN | MII_CR_FULL_DUPLEX | MII_CR_SPEED_SELECT_MSB;
+        state.phy[PHY_STATUS as usize] = 0x7949 | MII_SR_LINK_STATUS | MII_SR_AUTONEG_COMPLETE;
+        let (adv, gtctrl) = LinkModes::ALL_SPEEDS.to_phy();
+        state.phy[PHY_AUTONEG_ADV as usize] = adv;
+        state.phy[PHY_1000T_CTRL as usize] = gtctrl;
+        state.phy[PHY_LP_ABILITY as usize] = adv;
+        state.phy[PHY_1000T_STATUS as usize] = SR_1000T_LP_FD_CAPS;
+        state.reset();
+        let sim = SimE1000 {
+            state: Rc::new(RefCell::new(state)),
+        };
+        sim.set_mac_address(SIM_MAC_ADDRESS);
+        sim
+    }
+
+    /// Burn a MAC address into the EEPROM, keeping its checksum valid
+    pub fn set_mac_address(&self, mac: [u8; 6]) {
+        for i in 0..3 {
+            self.eeprom_write(i, u16::from_le_bytes([mac[i * 2], mac[i * 2 + 1]]));
+        }
+        let state = self.state.borrow();
+        let sum = state.eeprom[..EEPROM_CHECKSUM_REG as usize]
+            .iter()
+            .fold(0u16, |sum, word| sum.wrapping_add(*word));
+        drop(state);
+        self.eeprom_write(EEPROM_CHECKSUM_REG as usize, EEPROM_SUM.wrapping_sub(sum));
+    }
+
+    /// Overwrite a word of the EEPROM as it is, without fixing the checksum
+    pub fn eeprom_write(&self, offset: usize, word: u16) {
+        self.state.borrow_mut().eeprom[offset] = word;
+    }
+
+    /// Look at a register without the side effects of a read
+    pub fn peek(&self, reg: usize) -> u32 {
+        self.state.borrow().regs[reg]
+    }
+
+    /// Plug or unplug the link partner, raising a link status change.
+    /// `status` takes the STATUS bits FD and SPEED of the link when it comes up.
+    pub fn set_link(&self, up: bool, status: u32) {
+        let mut state = self.state.borrow_mut();
+        state.plugged = up;
+        if up {
+            state.link_change(E1000_STATUS_LU | status & (E1000_STATUS_FD | E1000_STATUS_SPEED_MASK));
+        } else {
+            state.link_change(0);
+        }
+    }
+
+    /// Change what the link partner advertises, auto-negotiating again
+    /// unless the PHY is forced
+    pub fn set_link_partner(&self, modes: LinkModes) {
+        let mut state = self.state.borrow_mut();
+        state.partner = modes;
+        if state.phy[PHY_CTRL as usize] & MII_CR_AUTO_NEG_EN != 0 {
+            state.phy_autoneg();
+        }
+    }
+
+    /// A frame arrives from the wire.
+    /// Returns false if the device dropped it.
+    pub fn receive(&self, frame: &[u8]) -> bool {
+        self.state.borrow_mut().receive(frame)
+    }
+
+    /// Take the oldest frame the device has put on the wire
+    pub fn take_transmitted(&self) -> Option<Vec<u8>> {
+        self.state.borrow_mut().tx_frames.pop_front()
+    }
+
+    /// Whether the device is asserting its interrupt line
+    pub fn interrupt_pending(&self) -> bool {
+        let state = self.state.borrow();
+        state.regs[E1000_ICR] & state.regs[E1000_IMS] != 0
+    }
+}
+
+impl Default for SimE1000 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl E1000Regs for SimE1000 {
+    fn read(&self, reg: usize) -> u32 {
+        let mut state = self.state.borrow_mut();
+        let value = state.regs[reg];
+        if reg == E1000_ICR {
+            // Read to clear
+            state.regs[E1000_ICR] = 0;
+        }
+        if (E1000_CRCERRS..=E1000_TSCTFC).contains(&reg) && !STATS_LOW.contains(&reg) {
+            // Statistics clear on read, the high register of a pair clearing both
+            state.regs[reg] = 0;
+            if STATS_LOW.contains(&(reg - 1)) {
+                state.regs[reg - 1] = 0;
+            }
+        }
+        value
+    }
+
+    fn write(&mut self, reg: usize, value: u32) {
+        let mut state = self.state.borrow_mut();
+        match reg {
+            E1000_CTL => {
+                if value & E1000_CTL_RST != 0 {
+                    state.reset();
+                } else {
+                    state.regs[E1000_CTL] = value;
+                }
+            }
+            E1000_STAT => {}
+            E1000_EERD => state.eeprom_read(value),
+            E1000_MDIC => state.mdic(value),
+            E1000_ICR => state.regs[E1000_ICR] &= !value,
+            E1000_ICS => state.raise(value),
+            E1000_IMS => state.regs[E1000_IMS] |= value,
+            E1000_IMC => state.regs[E1000_IMS] &= !value,
+            E1000_TDT => {
+                state.regs[E1000_TDT] = value;
+                state.transmit();
+            }
+            _ => state.regs[reg] = value,
+        }
+    }
+}
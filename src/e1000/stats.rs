@@ -0,0 +1,194 @@
+// Statistics: the clear-on-read counters at 0x4000 added up into 64-bit totals [E1000 13.7]
+use super::e1000::{E1000Device, KernelFunc};
+use super::e1000_const::*;
+use super::regs::E1000Regs;
+
+/// Totals of the hardware statistics counters since the device was created,
+/// brought up to date by `update_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HwStats {
+    /// Packets received with a CRC error
+    pub crcerrs: u64,
+    /// Packets received with an alignment error (MII)
+    pub algnerrc: u64,
+    /// Symbol errors
+    pub symerrs: u64,
+    /// Packets received with RX_ER signalled
+    pub rxerrc: u64,
+    /// Packets missed for lack of room in the receive FIFO or ring
+    pub mpc: u64,
+    /// Packets sent after a single collision
+    pub scc: u64,
+    /// Packets not sent after 16 collisions
+    pub ecol: u64,
+    /// Packets sent after several collisions
+    pub mcc: u64,
+    /// Late collisions
+    pub latecol: u64,
+    /// Collisions
+    pub colc: u64,
+    /// Transmissions deferred
+    pub dc: u64,
+    /// Transmissions without carrier sense
+    pub tncrs: u64,
+    /// Sequence errors
+    pub sec: u64,
+    /// Carrier extension errors
+    pub cexterr: u64,
+    /// Packets received with a length error
+    pub rlec: u64,
+    /// XON frames received
+    pub xonrxc: u64,
+    /// XON frames sent
+    pub xontxc: u64,
+    /// XOFF frames received
+    pub xoffrxc: u64,
+    /// XOFF frames sent
+    pub xofftxc: u64,
+    /// Unsupported flow control frames received
+    pub fcruc: u64,
+    /// Packets received of 64 bytes
+    pub prc64: u64,
+    /// Packets received of 65 to 127 bytes
+    pub prc127: u64,
+    /// Packets received of 128 to 255 bytes
+    pub prc255: u64,
+    /// Packets received of 256 to 511 bytes
+    pub prc511: u64,
+    /// Packets received of 512 to 1023 bytes
+    pub prc1023: u64,
+    /// Packets received of 1024 bytes or more
+    pub prc1522: u64,
+    /// Good packets received
+    pub gprc: u64,
+    /// Broadcast packets received
+    pub bprc: u64,
+    /// Multicast packets received
+    pub mprc: u64,
+    /// Good packets sent
+    pub gptc: u64,
+    /// Octets of the good packets received
+    pub gorc: u64,
+    /// Octets of the good packets sent
+    pub gotc: u64,
+    /// Times the rx ring had no free descriptor
+    pub rnbc: u64,
+    /// Packets received shorter than 64 bytes
+    pub ruc: u64,
+    /// Fragments received shorter than 64 bytes with a bad CRC
+    pub rfc: u64,
+    /// Packets received longer than allowed
+    pub roc: u64,
+    /// Packets received longer than allowed with a bad CRC
+    pub rjc: u64,
+    /// Management packets received
+    pub mgtprc: u64,
+    /// Management packets dropped
+    pub mgtpdc: u64,
+    /// Management packets sent
+    pub mgtptc: u64,
+    /// Octets received, bad packets included
+    pub tor: u64,
+    /// Octets sent, bad packets included
+    pub tot: u64,
+    /// Packets received, bad ones included
+    pub tpr: u64,
+    /// Packets sent
+    pub tpt: u64,
+    /// Packets sent of 64 bytes
+    pub ptc64: u64,
+    /// Packets sent of 65 to 127 bytes
+    pub ptc127: u64,
+    /// Packets sent of 128 to 255 bytes
+    pub ptc255: u64,
+    /// Packets sent of 256 to 511 bytes
+    pub ptc511: u64,
+    /// Packets sent of 512 to 1023 bytes
+    pub ptc1023: u64,
+    /// Packets sent of 1024 bytes or more
+    pub ptc1522: u64,
+    /// Multicast packets sent
+    pub mptc: u64,
+    /// Broadcast packets sent
+    pub bptc: u64,
+    /// TCP segmentation contexts sent
+    pub tsctc: u64,
+    /// TCP segmentation contexts that failed
+    pub tsctfc: u64,
+}
+
+/// Read a 64-bit counter, the low register first as reading the high one clears both
+fn read_counter64<R: E1000Regs>(regs: &R, low: usize) -> u64 {
+    let low_value = regs.read(low) as u64;
+    low_value | (regs.read(low + 1) as u64) << 32
+}
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Add the hardware counters into the totals, which clears the counters.
+    /// Call it often enough that no 32-bit counter wraps around, e.g. every few seconds.
+    pub fn update_stats(&mut self) -> &HwStats {
+        let regs = &self.regs;
+        let stats = &mut self.hw_stats;
+
+        stats.crcerrs += regs.read(E1000_CRCERRS) as u64;
+        stats.algnerrc += regs.read(E1000_ALGNERRC) as u64;
+        stats.symerrs += regs.read(E1000_SYMERRS) as u64;
+        stats.rxerrc += regs.read(E1000_RXERRC) as u64;
+        stats.mpc += regs.read(E1000_MPC) as u64;
+        stats.scc += regs.read(E1000_SCC) as u64;
+        stats.ecol += regs.read(E1000_ECOL) as u64;
+        stats.mcc += regs.read(E1000_MCC) as u64;
+        stats.latecol += regs.read(E1000_LATECOL) as u64;
+        stats.colc += regs.read(E1000_COLC) as u64;
+        stats.dc += regs.read(E1000_DC) as u64;
+        stats.tncrs += regs.read(E1000_TNCRS) as u64;
+        stats.sec += regs.read(E1000_SEC) as u64;
+        stats.cexterr += regs.read(E1000_CEXTERR) as u64;
+        stats.rlec += regs.read(E1000_RLEC) as u64;
+        stats.xonrxc += regs.read(E1000_XONRXC) as u64;
+        stats.xontxc += regs.read(E1000_XONTXC) as u64;
+        stats.xoffrxc += regs.read(E1000_XOFFRXC) as u64;
+        stats.xofftxc += regs.read(E1000_XOFFTXC) as u64;
+        stats.fcruc += regs.read(E1000_FCRUC) as u64;
+        stats.prc64 += regs.read(E1000_PRC64) as u64;
+        stats.prc127 += regs.read(E1000_PRC127) as u64;
+        stats.prc255 += regs.read(E1000_PRC255) as u64;
+        stats.prc511 += regs.read(E1000_PRC511) as u64;
+        stats.prc1023 += regs.read(E1000_PRC1023) as u64;
+        stats.prc1522 += regs.read(E1000_PRC1522) as u64;
+        stats.gprc += regs.read(E1000_GPRC) as u64;
+        stats.bprc += regs.read(E1000_BPRC) as u64;
+        stats.mprc += regs.read(E1000_MPRC) as u64;
+        stats.gptc += regs.read(E1000_GPTC) as u64;
+        stats.gorc += read_counter64(regs, E1000_GORCL);
+        stats.gotc += read_counter64(regs, E1000_GOTCL);
+        stats.rnbc += regs.read(E1000_RNBC) as u64;
+        stats.ruc += regs.read(E1000_RUC) as u64;
+        stats.rfc += regs.read(E1000_RFC) as u64;
+        stats.roc += regs.read(E1000_ROC) as u64;
+        stats.rjc += regs.read(E1000_RJC) as u64;
+        stats.mgtprc += regs.read(E1000_MGTPRC) as u64;
+        stats.mgtpdc += regs.read(E1000_MGTPDC) as u64;
+        stats.mgtptc += regs.read(E1000_MGTPTC) as u64;
+        stats.tor += read_counter64(regs, E1000_TORL);
+        stats.tot += read_counter64(regs, E1000_TOTL);
+        stats.tpr += regs.read(E1000_TPR) as u64;
+        stats.tpt += regs.read(E1000_TPT) as u64;
+        stats.ptc64 += regs.read(E1000_PTC64) as u64;
+        stats.ptc127 += regs.read(E1000_PTC127) as u64;
+        stats.ptc255 += regs.read(E1000_PTC255) as u64;
+        stats.ptc511 += regs.read(E1000_PTC511) as u64;
+        stats.ptc1023 += regs.read(E1000_PTC1023) as u64;
+        stats.ptc1522 += regs.read(E1000_PTC1522) as u64;
+        stats.mptc += regs.read(E1000_MPTC) as u64;
+        stats.bptc += regs.read(E1000_BPTC) as u64;
+        stats.tsctc += regs.read(E1000_TSCTC) as u64;
+        stats.tsctfc += regs.read(E1000_TSCTFC) as u64;
+        stats
+    }
+
+    /// The totals as of the last `update_stats`
+    pub fn hw_stats(&self) -> &HwStats {
+        &self.hw_stats
+    }
+}
@@ -0,0 +1,1651 @@
+// Host-side tests of the e1000 driver, without a real NIC
+use super::e1000_const::*;
+use super::*;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::ptr::{read_volatile, write_volatile};
+
+fn dma_read<T>(dma: usize) -> T {
+    unsafe { read_volatile(sim_dma_to_virt(dma) as *const T) }
+}
+
+fn dma_write<T>(dma: usize, value: T) {
+    unsafe { write_volatile(sim_dma_to_virt(dma) as *mut T, value) }
+}
+
+fn mock_device() -> (E1000Device<'static, SimKernelFunc, MemRegs>, MemRegs) {
+    let regs = MemRegs::new();
+    let dev = E1000Device::with_regs(SimKernelFunc, regs.clone(), E1000Config::default()).unwrap();
+    (dev, regs)
+}
+
+fn sim_device() -> (E1000Device<'static, SimKernelFunc, SimE1000>, SimE1000) {
+    let sim = SimE1000::new();
+    let dev = E1000Device::with_regs(SimKernelFunc, sim.clone(), E1000Config::default()).unwrap();
+    (dev, sim)
+}
+
+/// A broadcast frame of `len` bytes, filled with `fill` after the ethernet header
+fn frame(len: usize, fill: u8) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(len);
+    frame.extend_from_slice(&[0xff; 6]);
+    frame.extend_from_slice(&[0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+    frame.extend_from_slice(&[0x08, 0x00]);
+    frame.resize(len, fill);
+    frame
+}
+
+/// DMA address of the descriptor `index` in the ring at `base`
+fn desc_addr(regs: &MemRegs, base: usize, index: usize) -> usize {
+    let ring = regs.read(base) as usize | (regs.read(base + 1) as usize) << 32;
+    ring + index * 16
+}
+
+#[test]
+fn init_programs_rings() {
+    let (_dev, regs) = mock_device();
+
+    assert_ne!(regs.read(E1000_TDBAL), 0);
+    assert_eq!(regs.read(E1000_TDLEN), 256 * 16);
+    assert_eq!(regs.read(E1000_TDH), 0);
+    assert_eq!(regs.read(E1000_TDT), 0);
+    assert_ne!(regs.read(E1000_TCTL) & E1000_TCTL_EN, 0);
+
+    assert_ne!(regs.read(E1000_RDBAL), 0);
+    assert_eq!(regs.read(E1000_RDLEN), 256 * 16);
+    assert_eq!(regs.read(E1000_RDH), 0);
+    assert_eq!(regs.read(E1000_RDT), 255);
+    assert_ne!(regs.read(E1000_RCTL) & E1000_RCTL_EN, 0);
+
+    // Every rx descriptor owns a buffer
+    for i in 0..256 {
+        assert_ne!(dma_read::<u64>(desc_addr(&regs, E1000_RDBAL, i)), 0);
+    }
+}
+
+#[test]
+fn transmit_fills_descriptor() {
+    let (mut dev, regs) = mock_device();
+    let frame = [0xa5u8; 60];
+
+    assert_eq!(dev.e1000_transmit(&frame), Ok(60));
+    assert_eq!(regs.read(E1000_TDT), 1);
+
+    let desc = desc_addr(&regs, E1000_TDBAL, 0);
+    let buf = dma_read::<u64>(desc) as usize;
+    assert_eq!(dma_read::<u16>(desc + 8), 60);
+    assert_eq!(
+        dma_read::<u8>(desc + 11) as u32,
+        E1000_TXD_CMD_RS | E1000_TXD_CMD_EOP
+    );
+    assert_eq!(dma_read::<u8>(desc + 12), 0);
+    assert_eq!(dma_read::<[u8; 60]>(buf), frame);
+}
+
+#[test]
+fn transmit_busy_descriptor() {
+    let (mut dev, regs) = mock_device();
+    assert_eq!(dev.tx_free_slots(), 255);
+
+    // The hardware never writes back DD, so the ring fills up
+    for i in 0..255 {
+        assert_eq!(dev.e1000_transmit(&[1; 60]), Ok(60), "packet {}", i);
+    }
+    assert_eq!(dev.tx_free_slots(), 0);
+    assert_eq!(dev.e1000_transmit(&[2; 60]), Err(E1000Error::RingFull));
+    assert_eq!(regs.read(E1000_TDT), 255);
+
+    // Until descriptor 0 is done
+    dma_write(desc_addr(&regs, E1000_TDBAL, 0) + 12, E1000_TXD_STAT_DD as u8);
+    assert_eq!(dev.e1000_transmit(&[2; 60]), Ok(60));
+    assert_eq!(regs.read(E1000_TDT), 0);
+    assert_eq!(dev.tx_clean(), TxCompletion { packets: 1, bytes: 60 });
+}
+
+#[test]
+fn recv_done_descriptors() {
+    let (mut dev, regs) = mock_device();
+    assert!(dev.e1000_recv().is_none());
+
+    // Play the hardware: write two frames into descriptors 0 and 1
+    for (i, len) in [(0usize, 64u16), (1, 100)] {
+        let desc = desc_addr(&regs, E1000_RDBAL, i);
+        let buf = dma_read::<u64>(desc) as usize;
+        for off in 0..len as usize {
+            dma_write(buf + off, i as u8 + 1);
+        }
+        dma_write(desc + 8, len);
+        dma_write(desc + 12, (E1000_RXD_STAT_DD | E1000_RXD_STAT_EOP) as u8);
+    }
+
+    let packets = dev.e1000_recv().unwrap();
+    assert_eq!(packets.len(), 2);
+    assert_eq!(packets[0].len(), 64);
+    assert!(packets[0].iter().all(|b| *b == 1));
+    assert_eq!(packets[1].len(), 100);
+    assert!(packets[1].iter().all(|b| *b == 2));
+
+    // Both descriptors were handed back to the hardware
+    assert_eq!(regs.read(E1000_RDT), 1);
+    assert_eq!(dma_read::<u8>(desc_addr(&regs, E1000_RDBAL, 0) + 12), 0);
+    assert!(dev.e1000_recv().is_none());
+}
+
+#[test]
+fn intr_acknowledges_causes() {
+    let (mut dev, mut regs) = mock_device();
+    assert_eq!(regs.read(E1000_IMS), dev.interrupt_mask().bits());
+    assert_eq!(dev.interrupt_mask(), InterruptCause::DEFAULT);
+
+    regs.write(E1000_ICR, E1000_IMS_RXT0 | E1000_ICR_LSC);
+    assert_eq!(dev.e1000_intr(), InterruptCause::RXT0 | InterruptCause::LSC);
+
+    dev.e1000_irq_disable();
+    assert_eq!(regs.read(E1000_IMC), !0);
+}
+
+#[test]
+fn sim_transmit_end_to_end() {
+    let (mut dev, sim) = sim_device();
+
+    for i in 0..4 {
+        let pkt = frame(60 + i, i as u8);
+        assert_eq!(dev.e1000_transmit(&pkt), Ok(pkt.len()));
+        assert_eq!(sim.take_transmitted().unwrap(), pkt);
+    }
+    assert!(sim.take_transmitted().is_none());
+    assert_eq!(sim.peek(E1000_TDH), 4);
+    assert_eq!(sim.peek(E1000_TDT), 4);
+}
+
+#[test]
+fn sim_transmit_ring_wraparound() {
+    let (mut dev, sim) = sim_device();
+
+    // Several laps around the 256-entry ring
+    for i in 0..700 {
+        let pkt = frame(64, i as u8);
+        assert_eq!(dev.e1000_transmit(&pkt), Ok(64), "packet {}", i);
+        assert_eq!(sim.take_transmitted().unwrap(), pkt);
+    }
+    assert_eq!(sim.peek(E1000_TDT), 700 % 256);
+    assert_eq!(sim.peek(E1000_TDH), 700 % 256);
+}
+
+#[test]
+fn sim_receive_end_to_end() {
+    let (mut dev, sim) = sim_device();
+    assert!(!sim.interrupt_pending());
+
+    let frames: Vec<Vec<u8>> = (0..3).map(|i| frame(100 + i, i as u8)).collect();
+    for f in frames.iter() {
+        assert!(sim.receive(f));
+    }
+    assert!(sim.interrupt_pending());
+    assert!(dev.e1000_intr().contains(InterruptCause::RXT0));
+    assert!(!sim.interrupt_pending());
+
+    assert_eq!(dev.e1000_recv().unwrap(), frames);
+    assert!(dev.e1000_recv().is_none());
+}
+
+#[test]
+fn sim_receive_ring_wraparound() {
+    let (mut dev, sim) = sim_device();
+
+    for lap in 0..5 {
+        for i in 0..100 {
+            assert!(sim.receive(&frame(64, i as u8)), "lap {} frame {}", lap, i);
+        }
+        let packets = dev.e1000_recv().unwrap();
+        assert_eq!(packets.len(), 100);
+        for (i, p) in packets.iter().enumerate() {
+            assert_eq!(*p, frame(64, i as u8));
+        }
+    }
+    assert_eq!(sim.peek(E1000_RDH), 500 % 256);
+    assert_eq!(sim.peek(E1000_RDT), (500 + 255) % 256);
+}
+
+#[test]
+fn sim_receive_ring_full() {
+    let (mut dev, sim) = sim_device();
+
+    // The driver hands 255 of the 256 descriptors to the hardware
+    for i in 0..255 {
+        assert!(sim.receive(&frame(64, i as u8)));
+    }
+    assert!(!sim.receive(&frame(64, 0)));
+    assert_ne!(sim.peek(E1000_ICR) & E1000_ICR_RXO, 0);
+
+    assert_eq!(dev.e1000_recv().unwrap().len(), 255);
+    assert!(sim.receive(&frame(64, 0)));
+}
+
+#[test]
+fn eeprom_mac_address() {
+    let (mut dev, sim) = sim_device();
+
+    assert_eq!(dev.mac_address(), Ok(SIM_MAC_ADDRESS));
+    // e1000_init filters on the permanent address
+    assert_eq!(sim.peek(E1000_RA), 0x12005452);
+    assert_eq!(sim.peek(E1000_RA + 1), 0x5634 | E1000_RAH_AV);
+
+    let mac = [0x00, 0x1b, 0x21, 0xaa, 0xbb, 0xcc];
+    sim.set_mac_address(mac);
+    assert_eq!(dev.mac_address(), Ok(mac));
+}
+
+#[test]
+fn eeprom_bad_checksum() {
+    let (mut dev, sim) = sim_device();
+
+    sim.eeprom_write(0x10, 0x1234);
+    assert_eq!(dev.mac_address(), Err(E1000Error::EepromChecksum));
+
+    // Nothing answers EERD on a plain register file
+    let (mut dev, _regs) = mock_device();
+    assert_eq!(dev.mac_address(), Err(E1000Error::EepromTimeout));
+}
+
+#[test]
+fn mac_address_from_config() {
+    let mac = [0x52, 0x54, 0x00, 0x6c, 0xf8, 0x88];
+    let config = E1000Config::new().mac_address(mac);
+
+    // Nothing answers EERD on a plain register file, RA[0] takes the chosen address
+    let dev = E1000Device::with_regs(SimKernelFunc, MemRegs::new(), config).unwrap();
+    assert_eq!(dev.e1000_rar_get(0).map(|ra| ra.mac), Some(mac));
+    // Over the permanent address as well
+    let sim = SimE1000::new();
+    let mut dev = E1000Device::with_regs(SimKernelFunc, sim.clone(), config).unwrap();
+    assert_eq!(dev.e1000_rar_get(0).map(|ra| ra.mac), Some(mac));
+    assert_eq!(dev.mac_address(), Ok(SIM_MAC_ADDRESS));
+}
+
+/// A frame of `len` bytes from `src` to `dst`
+fn frame_to(dst: [u8; 6], src: [u8; 6], len: usize) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(len);
+    frame.extend_from_slice(&dst);
+    frame.extend_from_slice(&src);
+    frame.extend_from_slice(&[0x08, 0x00]);
+    frame.resize(len, 0x5a);
+    frame
+}
+
+const PEER_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+
+#[test]
+fn unicast_filters() {
+    let (mut dev, sim) = sim_device();
+    let extra = [0x02, 0x00, 0x00, 0x00, 0x00, 0x10];
+
+    assert!(sim.receive(&frame_to(SIM_MAC_ADDRESS, PEER_MAC, 64)));
+    assert!(!sim.receive(&frame_to(extra, PEER_MAC, 64)));
+
+    assert_eq!(dev.add_unicast_filter(extra, AddressSelect::Destination), Ok(1));
+    // Adding it twice reuses the entry
+    assert_eq!(dev.add_unicast_filter(extra, AddressSelect::Destination), Ok(1));
+    assert_eq!(
+        dev.unicast_filters(),
+        [ReceiveAddress { index: 1, mac: extra, select: AddressSelect::Destination }]
+    );
+    assert!(sim.receive(&frame_to(extra, PEER_MAC, 64)));
+
+    assert_eq!(dev.remove_unicast_filter(extra, AddressSelect::Destination), Ok(()));
+    assert!(dev.unicast_filters().is_empty());
+    assert!(!sim.receive(&frame_to(extra, PEER_MAC, 64)));
+    assert_eq!(dev.remove_unicast_filter(extra, AddressSelect::Destination), Err(E1000Error::NotFound));
+}
+
+#[test]
+fn unicast_filters_full() {
+    let (mut dev, _sim) = sim_device();
+
+    for i in 1..16 {
+        let mac = [0x02, 0, 0, 0, 0, i as u8];
+        assert_eq!(dev.add_unicast_filter(mac, AddressSelect::Destination), Ok(i));
+    }
+    assert_eq!(
+        dev.add_unicast_filter([0x02, 0, 0, 0, 1, 0], AddressSelect::Destination),
+        Err(E1000Error::FilterFull)
+    );
+    assert_eq!(
+        dev.add_unicast_filter([0x01, 0, 0x5e, 0, 0, 1], AddressSelect::Destination),
+        Err(E1000Error::InvalidParam)
+    );
+    assert_eq!(dev.unicast_filters().len(), 15);
+
+    // Source address filter
+    let (mut dev, sim) = sim_device();
+    let stranger = [0x02, 0, 0, 0, 0, 0x77];
+    assert!(!sim.receive(&frame_to(stranger, PEER_MAC, 64)));
+    dev.add_unicast_filter(PEER_MAC, AddressSelect::Source).unwrap();
+    assert!(sim.receive(&frame_to(stranger, PEER_MAC, 64)));
+
+    // Changing the primary address
+    let mac = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55];
+    dev.set_mac_address(mac).unwrap();
+    assert!(sim.receive(&frame_to(mac, [0x02, 0, 0, 0, 0, 2], 64)));
+    assert!(!sim.receive(&frame_to(SIM_MAC_ADDRESS, [0x02, 0, 0, 0, 0, 2], 64)));
+}
+
+#[test]
+fn multicast_hash_filter() {
+    let (mut dev, sim) = sim_device();
+    let mdns = [0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb];
+    // Only differs from mdns in bits outside [47:36], so it shares the hash bucket
+    let shared = [0x01, 0x00, 0x5e, 0x00, 0x01, 0xfb];
+    let other = [0x33, 0x33, 0x00, 0x00, 0x00, 0x01];
+    assert_eq!(
+        MulticastOffset::Bits47_36.hash(&mdns),
+        MulticastOffset::Bits47_36.hash(&shared)
+    );
+
+    assert!(!sim.receive(&frame_to(mdns, PEER_MAC, 64)));
+    dev.add_multicast(mdns).unwrap();
+    dev.add_multicast(shared).unwrap();
+    assert!(sim.receive(&frame_to(mdns, PEER_MAC, 64)));
+    assert!(!sim.receive(&frame_to(other, PEER_MAC, 64)));
+
+    // The bucket stays set while `shared` still references it
+    dev.remove_multicast(mdns).unwrap();
+    assert!(sim.receive(&frame_to(mdns, PEER_MAC, 64)));
+    dev.remove_multicast(shared).unwrap();
+    assert!(!sim.receive(&frame_to(mdns, PEER_MAC, 64)));
+    assert_eq!(dev.remove_multicast(shared), Err(E1000Error::NotFound));
+    assert!(dev.add_multicast(PEER_MAC).is_err());
+
+    dev.set_multicast_list(&[other, mdns]).unwrap();
+    assert_eq!(dev.multicast_list(), [other, mdns]);
+    assert!(sim.receive(&frame_to(other, PEER_MAC, 64)));
+    dev.set_multicast_offset(MulticastOffset::Bits43_32);
+    assert!(sim.receive(&frame_to(other, PEER_MAC, 64)));
+    assert!(sim.receive(&frame_to(mdns, PEER_MAC, 64)));
+    dev.set_multicast_list(&[]).unwrap();
+    assert!(!sim.receive(&frame_to(other, PEER_MAC, 64)));
+    assert!((0..128).all(|i| sim.peek(E1000_MTA + i) == 0));
+
+    dev.set_all_multicast(true);
+    assert!(sim.receive(&frame_to(other, PEER_MAC, 64)));
+    dev.set_all_multicast(false);
+    assert!(!sim.receive(&frame_to(other, PEER_MAC, 64)));
+}
+
+#[test]
+fn promiscuous_rx_modes() {
+    let (mut dev, sim) = sim_device();
+    let stranger = [0x02, 0, 0, 0, 0, 0x77];
+    let group = [0x01, 0x00, 0x5e, 0x01, 0x02, 0x03];
+    assert_eq!(dev.rx_mode(), RxMode::Normal);
+
+    dev.set_rx_mode(RxMode::Promiscuous);
+    assert!(sim.receive(&frame_to(stranger, PEER_MAC, 64)));
+    assert!(sim.receive(&frame_to(group, PEER_MAC, 64)));
+    // Asking for all-multicast doesn't leave promiscuous mode
+    dev.set_all_multicast(false);
+    assert_eq!(dev.rx_mode(), RxMode::Promiscuous);
+
+    dev.set_rx_mode(RxMode::PromiscuousBadPackets);
+    let rctl = sim.peek(E1000_RCTL);
+    assert_eq!(
+        rctl & (E1000_RCTL_UPE | E1000_RCTL_MPE | E1000_RCTL_SBP),
+        E1000_RCTL_UPE | E1000_RCTL_MPE | E1000_RCTL_SBP
+    );
+    // The receiver kept running with the same buffers
+    assert_ne!(rctl & E1000_RCTL_EN, 0);
+    assert_eq!(rctl & (E1000_RCTL_BSEX | 0x30000), E1000_RCTL_SZ_2048);
+
+    dev.set_rx_mode(RxMode::AllMulticast);
+    assert!(!sim.receive(&frame_to(stranger, PEER_MAC, 64)));
+    assert!(sim.receive(&frame_to(group, PEER_MAC, 64)));
+
+    dev.set_all_multicast(false);
+    assert_eq!(dev.rx_mode(), RxMode::Normal);
+    assert!(!sim.receive(&frame_to(group, PEER_MAC, 64)));
+    assert_eq!(dev.e1000_recv().unwrap().len(), 3);
+}
+
+/// Insert an 802.1Q tag into an untagged frame
+fn tagged(frame: &[u8], tci: u16) -> Vec<u8> {
+    let mut tagged = frame[..12].to_vec();
+    tagged.extend_from_slice(&[0x81, 0x00]);
+    tagged.extend_from_slice(&tci.to_be_bytes());
+    tagged.extend_from_slice(&frame[12..]);
+    tagged
+}
+
+#[test]
+fn vlan_tag_insertion() {
+    let (mut dev, sim) = sim_device();
+    let pkt = frame_to(PEER_MAC, SIM_MAC_ADDRESS, 64);
+
+    // Insertion needs CTRL.VME
+    assert_eq!(dev.e1000_transmit_vlan(&pkt, 100), Err(E1000Error::InvalidConfig));
+    dev.set_vlan_offload(true);
+    assert!(dev.vlan_offload());
+    assert_eq!(dev.e1000_transmit_vlan(&pkt, (5 << 13) | 100), Ok(64));
+    assert_eq!(sim.take_transmitted().unwrap(), tagged(&pkt, (5 << 13) | 100));
+
+    // Plain transmits stay untagged
+    assert_eq!(dev.e1000_transmit(&pkt), Ok(64));
+    assert_eq!(sim.take_transmitted().unwrap(), pkt);
+}
+
+#[test]
+fn vlan_tag_stripping() {
+    let (mut dev, sim) = sim_device();
+    let pkt = frame_to(SIM_MAC_ADDRESS, PEER_MAC, 64);
+
+    // Without VME the tag stays in the data
+    assert!(sim.receive(&tagged(&pkt, 42)));
+    let rx = dev.e1000_recv_packets().unwrap();
+    assert_eq!(rx[0].data, tagged(&pkt, 42));
+    assert_eq!(rx[0].meta.vlan_tag, None);
+
+    dev.set_vlan_offload(true);
+    assert!(sim.receive(&tagged(&pkt, 42)));
+    assert!(sim.receive(&pkt));
+    let rx = dev.e1000_recv_packets().unwrap();
+    assert_eq!(rx[0].data, pkt);
+    assert_eq!(rx[0].meta.vlan_tag, Some(42));
+    assert_eq!(rx[1].meta.vlan_tag, None);
+}
+
+#[test]
+fn vlan_filter_table() {
+    let (mut dev, sim) = sim_device();
+    let pkt = frame_to(SIM_MAC_ADDRESS, PEER_MAC, 64);
+
+    dev.set_vlan_filtering(true);
+    assert!(!sim.receive(&tagged(&pkt, 10)));
+    // Untagged packets aren't filtered
+    assert!(sim.receive(&pkt));
+
+    dev.vlan_filter_add(10).unwrap();
+    dev.vlan_filter_add(4095).unwrap();
+    assert!(dev.vlan_filter_add(4096).is_err());
+    assert!(dev.vlan_filter_contains(10));
+    assert!(!dev.vlan_filter_contains(11));
+    assert!(sim.receive(&tagged(&pkt, 10)));
+    assert!(sim.receive(&tagged(&pkt, (7 << 13) | 4095)));
+    assert!(!sim.receive(&tagged(&pkt, 11)));
+
+    dev.vlan_filter_remove(10).unwrap();
+    assert!(!sim.receive(&tagged(&pkt, 10)));
+    dev.set_vlan_filtering(false);
+    assert!(sim.receive(&tagged(&pkt, 10)));
+    assert_eq!(dev.e1000_recv().unwrap().len(), 4);
+}
+
+/// Ones' complement sum of big-endian 16-bit words, not complemented
+fn ones_sum(data: &[u8]) -> u16 {
+    let mut sum = 0u32;
+    for word in data.chunks(2) {
+        sum += u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    sum as u16
+}
+
+/// An IPv4 packet of protocol `proto` carrying `l4_len` bytes, with the IP checksum zeroed
+/// and the L4 checksum at `csum_offset` seeded with the pseudo header sum
+fn ipv4_packet(proto: u8, l4_len: usize, csum_offset: usize) -> Vec<u8> {
+    let mut pkt = frame_to(PEER_MAC, SIM_MAC_ADDRESS, 14);
+    pkt[12..14].copy_from_slice(&[0x08, 0x00]);
+    pkt.extend_from_slice(&[0x45, 0, 0, 0, 0x12, 0x34, 0x40, 0, 64, proto, 0, 0]);
+    pkt[16..18].copy_from_slice(&((20 + l4_len) as u16).to_be_bytes());
+    pkt.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+    pkt.extend((0..l4_len).map(|i| i as u8));
+    pkt[34 + csum_offset..34 + csum_offset + 2].fill(0);
+
+    let mut pseudo = pkt[26..34].to_vec();
+    pseudo.extend_from_slice(&[0, proto]);
+    pseudo.extend_from_slice(&(l4_len as u16).to_be_bytes());
+    let seed = ones_sum(&pseudo).to_be_bytes();
+    pkt[34 + csum_offset..34 + csum_offset + 2].copy_from_slice(&seed);
+    pkt
+}
+
+/// Check the IPv4 header and L4 checksums of a packet built by `ipv4_packet`
+fn assert_ipv4_checksums(pkt: &[u8], proto: u8) {
+    assert_eq!(ones_sum(&pkt[14..34]), 0xFFFF);
+    let mut pseudo = pkt[26..34].to_vec();
+    pseudo.extend_from_slice(&[0, proto]);
+    pseudo.extend_from_slice(&((pkt.len() - 34) as u16).to_be_bytes());
+    pseudo.extend_from_slice(&pkt[34..]);
+    assert_eq!(ones_sum(&pseudo), 0xFFFF);
+}
+
+#[test]
+fn tx_checksum_offload() {
+    let (mut dev, sim) = sim_device();
+    let tcp = TxOffload {
+        ip_offset: 14,
+        l4_offset: 34,
+        ip_checksum: true,
+        l4_checksum: Some(L4Protocol::Tcp),
+        vlan_tag: None,
+        mss: None,
+    };
+    let udp = TxOffload {
+        l4_checksum: Some(L4Protocol::Udp),
+        ..tcp
+    };
+
+    // A context descriptor, then the data descriptor
+    let pkt = ipv4_packet(6, 20 + 101, 16);
+    assert_eq!(dev.e1000_transmit_offload(&pkt, &tcp), Ok(pkt.len()));
+    assert_eq!(sim.peek(E1000_TDT), 2);
+    let sent = sim.take_transmitted().unwrap();
+    assert_ipv4_checksums(&sent, 6);
+    assert_eq!(sent[34..50], pkt[34..50]);
+
+    // The context is still loaded for the next TCP packet
+    let pkt = ipv4_packet(6, 20 + 40, 16);
+    assert_eq!(dev.e1000_transmit_offload(&pkt, &tcp), Ok(pkt.len()));
+    assert_eq!(sim.peek(E1000_TDT), 3);
+    assert_ipv4_checksums(&sim.take_transmitted().unwrap(), 6);
+
+    let pkt = ipv4_packet(17, 8 + 33, 6);
+    assert_eq!(dev.e1000_transmit_offload(&pkt, &udp), Ok(pkt.len()));
+    assert_eq!(sim.peek(E1000_TDT), 5);
+    assert_ipv4_checksums(&sim.take_transmitted().unwrap(), 17);
+
+    // Without offloads the packet goes out as it is
+    assert_eq!(
+        dev.e1000_transmit_offload(&pkt, &TxOffload::default()),
+        Ok(pkt.len())
+    );
+    assert_eq!(sim.take_transmitted().unwrap(), pkt);
+}
+
+#[test]
+fn tx_checksum_offload_invalid() {
+    let (mut dev, sim) = sim_device();
+    let pkt = ipv4_packet(17, 8, 6);
+    let mut offload = TxOffload {
+        ip_offset: 14,
+        l4_offset: 30,
+        ip_checksum: true,
+        l4_checksum: Some(L4Protocol::Udp),
+        vlan_tag: None,
+        mss: None,
+    };
+
+    // The L4 header can't start inside the IPv4 header
+    assert_eq!(dev.e1000_transmit_offload(&pkt, &offload), Err(E1000Error::InvalidParam));
+    // Nor its checksum lie past the end of the packet
+    offload.l4_offset = 40;
+    assert_eq!(dev.e1000_transmit_offload(&pkt, &offload), Err(E1000Error::InvalidParam));
+    // Checksum offsets beyond a byte
+    let far = TxOffload {
+        ip_offset: 250,
+        l4_offset: 255,
+        l4_checksum: None,
+        ..offload
+    };
+    assert_eq!(dev.e1000_transmit_offload(&[0; 300], &far), Err(E1000Error::InvalidParam));
+    let far = TxOffload {
+        ip_offset: 230,
+        l4_offset: 250,
+        ..offload
+    };
+    assert_eq!(dev.e1000_transmit_offload(&[0; 300], &far), Err(E1000Error::InvalidParam));
+    // Tags need VLAN offload
+    offload.l4_offset = 34;
+    offload.vlan_tag = Some(100);
+    assert_eq!(
+        dev.e1000_transmit_offload(&pkt, &offload),
+        Err(E1000Error::InvalidConfig)
+    );
+    assert_eq!(sim.peek(E1000_TDT), 0);
+    assert!(sim.take_transmitted().is_none());
+}
+
+/// A TCP/IPv4 packet for TSO carrying `payload_len` bytes, the TCP checksum
+/// seeded with the pseudo header sum without the length
+fn tso_packet(payload_len: usize) -> Vec<u8> {
+    let mut pkt = ipv4_packet(6, 20 + payload_len, 16);
+    pkt[38..42].copy_from_slice(&0x1000_0000u32.to_be_bytes()); // sequence number
+    pkt[46] = 5 << 4; // data offset
+    pkt[47] = 0x19; // FIN, PSH, ACK
+    let mut pseudo = pkt[26..34].to_vec();
+    pseudo.extend_from_slice(&[0, 6]);
+    let seed = ones_sum(&pseudo).to_be_bytes();
+    pkt[50..52].copy_from_slice(&seed);
+    pkt
+}
+
+#[test]
+fn tx_tcp_segmentation_offload() {
+    let (mut dev, sim) = sim_device();
+    let tso = TxOffload {
+        ip_offset: 14,
+        l4_offset: 34,
+        ip_checksum: true,
+        l4_checksum: Some(L4Protocol::Tcp),
+        vlan_tag: None,
+        mss: Some(1460),
+    };
+    let pkt = tso_packet(10000);
+
+    // A context descriptor and a chain of 5 data descriptors
+    assert_eq!(dev.e1000_transmit_offload(&pkt, &tso), Ok(pkt.len()));
+    assert_eq!(sim.peek(E1000_TDT), 6);
+
+    let mut payload = Vec::new();
+    for i in 0..7 {
+        let seg = sim.take_transmitted().unwrap();
+        let len = if i < 6 { 1460 } else { 10000 - 6 * 1460 };
+        assert_eq!(seg.len(), 54 + len);
+        assert_eq!(seg[..14], pkt[..14]);
+        assert_eq!(u16::from_be_bytes([seg[16], seg[17]]) as usize, 40 + len);
+        assert_eq!(u16::from_be_bytes([seg[18], seg[19]]), 0x1234 + i as u16);
+        assert_eq!(
+            u32::from_be_bytes([seg[38], seg[39], seg[40], seg[41]]),
+            0x1000_0000 + i as u32 * 1460
+        );
+        assert_eq!(seg[47], if i < 6 { 0x10 } else { 0x19 });
+        assert_ipv4_checksums(&seg, 6);
+        payload.extend_from_slice(&seg[54..]);
+    }
+    assert!(sim.take_transmitted().is_none());
+    assert_eq!(payload, pkt[54..]);
+}
+
+#[test]
+fn tx_tcp_segmentation_offload_invalid() {
+    let (mut dev, sim) = sim_device();
+    let tso = TxOffload {
+        ip_offset: 14,
+        l4_offset: 34,
+        ip_checksum: true,
+        l4_checksum: Some(L4Protocol::Tcp),
+        vlan_tag: None,
+        mss: Some(1460),
+    };
+
+    // Up to 64 KiB
+    let pkt = tso_packet(64 * 1024);
+    assert_eq!(dev.e1000_transmit_offload(&pkt, &tso), Err(E1000Error::InvalidParam));
+    // TCP only
+    let pkt = tso_packet(4000);
+    let udp = TxOffload {
+        l4_checksum: Some(L4Protocol::Udp),
+        ..tso
+    };
+    assert_eq!(dev.e1000_transmit_offload(&pkt, &udp), Err(E1000Error::InvalidParam));
+    let no_csum = TxOffload {
+        ip_checksum: false,
+        l4_checksum: None,
+        ..tso
+    };
+    assert_eq!(dev.e1000_transmit_offload(&pkt, &no_csum), Err(E1000Error::InvalidParam));
+    // The TCP checksum past the headers repeated in every segment
+    let mut short_header = pkt.clone();
+    short_header[46] = 4 << 4;
+    assert_eq!(dev.e1000_transmit_offload(&short_header, &tso), Err(E1000Error::InvalidParam));
+    assert_eq!(sim.peek(E1000_TDT), 0);
+    assert!(sim.take_transmitted().is_none());
+}
+
+#[test]
+fn tx_tcp_segmentation_offload_mss_beyond_mtu() {
+    let (mut dev, sim) = sim_device();
+    let tso = TxOffload {
+        ip_offset: 14,
+        l4_offset: 34,
+        ip_checksum: true,
+        l4_checksum: Some(L4Protocol::Tcp),
+        vlan_tag: None,
+        mss: Some(1500),
+    };
+    let pkt = tso_packet(4000);
+
+    // 54 bytes of headers and 1500 of payload don't fit a 1518 byte frame
+    assert_eq!(dev.e1000_transmit_offload(&pkt, &tso), Err(E1000Error::InvalidParam));
+    assert_eq!(sim.peek(E1000_TDT), 0);
+    // They do with a larger MTU
+    assert_eq!(dev.set_mtu(9000), Ok(()));
+    assert_eq!(dev.e1000_transmit_offload(&pkt, &tso), Ok(pkt.len()));
+    assert_eq!(sim.take_transmitted().unwrap().len(), 54 + 1500);
+}
+
+#[test]
+fn rx_checksum_offload() {
+    let (mut dev, sim) = sim_device();
+    assert!(dev.rx_checksum_offload());
+
+    // ipv4_packet leaves the checksums for the hardware, fill them in as a sender would
+    let mut tcp = ipv4_packet(6, 20 + 64, 16);
+    tcp[0..6].copy_from_slice(&SIM_MAC_ADDRESS);
+    let mut ip_sum = (!ones_sum(&tcp[14..34])).to_be_bytes();
+    tcp[24..26].copy_from_slice(&ip_sum);
+    let l4_sum = (!ones_sum(&tcp[34..])).to_be_bytes();
+    tcp[50..52].copy_from_slice(&l4_sum);
+    assert_ipv4_checksums(&tcp, 6);
+
+    assert!(sim.receive(&tcp));
+    let meta = dev.e1000_recv_packets().unwrap()[0].meta;
+    assert_eq!(meta.ip_checksum, Some(true));
+    assert_eq!(meta.l4_checksum, Some(true));
+    assert!(meta.checksum_verified());
+    assert_eq!(meta.csum, ones_sum(&tcp[14..]));
+
+    // A corrupted payload
+    let mut bad = tcp.clone();
+    bad[60] ^= 0xff;
+    assert!(sim.receive(&bad));
+    let meta = dev.e1000_recv_packets().unwrap()[0].meta;
+    assert_eq!(meta.ip_checksum, Some(true));
+    assert_eq!(meta.l4_checksum, Some(false));
+    assert!(!meta.checksum_verified());
+
+    // A corrupted IPv4 header
+    ip_sum[0] ^= 0xff;
+    bad = tcp.clone();
+    bad[24..26].copy_from_slice(&ip_sum);
+    assert!(sim.receive(&bad));
+    let meta = dev.e1000_recv_packets().unwrap()[0].meta;
+    assert_eq!(meta.ip_checksum, Some(false));
+    assert!(!meta.checksum_verified());
+
+    // Not IP
+    assert!(sim.receive(&frame_to(SIM_MAC_ADDRESS, PEER_MAC, 64)));
+    let meta = dev.e1000_recv_packets().unwrap()[0].meta;
+    assert_eq!((meta.ip_checksum, meta.l4_checksum), (None, None));
+
+    // Nothing is checked with the offload disabled
+    dev.set_rx_checksum_offload(false);
+    assert!(sim.receive(&tcp));
+    let meta = dev.e1000_recv_packets().unwrap()[0].meta;
+    assert_eq!((meta.ip_checksum, meta.l4_checksum), (None, None));
+    assert_eq!(meta.csum, ones_sum(&tcp[14..]));
+}
+
+#[test]
+fn jumbo_frames() {
+    let (mut dev, sim) = sim_device();
+    let jumbo = frame_to(SIM_MAC_ADDRESS, PEER_MAC, 9014);
+    assert_eq!(dev.mtu(), 1500);
+    assert!(!sim.receive(&jumbo));
+    assert_eq!(dev.e1000_transmit(&jumbo), Err(E1000Error::FrameTooLarge));
+
+    assert_eq!(dev.set_mtu(9000), Ok(()));
+    let rctl = sim.peek(E1000_RCTL);
+    assert_eq!(rctl & (E1000_RCTL_BSEX | E1000_RCTL_SZ_MASK), E1000_RCTL_BSEX | E1000_RCTL_SZ_4096);
+    assert_ne!(rctl & E1000_RCTL_LPE, 0);
+    assert_ne!(rctl & E1000_RCTL_EN, 0);
+
+    // Received over 3 descriptors, reassembled
+    let small = frame_to(SIM_MAC_ADDRESS, PEER_MAC, 64);
+    assert!(sim.receive(&jumbo));
+    assert!(sim.receive(&small));
+    assert_eq!(sim.peek(E1000_RDH), 4);
+    assert_eq!(dev.e1000_recv().unwrap(), [jumbo.clone(), small.clone()]);
+    assert_eq!(sim.peek(E1000_RDT), 3);
+
+    // Sent over 3 descriptors
+    assert_eq!(dev.e1000_transmit(&jumbo), Ok(9014));
+    assert_eq!(sim.peek(E1000_TDT), 3);
+    assert_eq!(sim.take_transmitted().unwrap(), jumbo);
+
+    // Back to the standard MTU
+    assert_eq!(dev.set_mtu(1500), Ok(()));
+    let rctl = sim.peek(E1000_RCTL);
+    assert_eq!(rctl & (E1000_RCTL_BSEX | E1000_RCTL_SZ_MASK | E1000_RCTL_LPE), E1000_RCTL_SZ_2048);
+    assert!(!sim.receive(&jumbo));
+    assert!(sim.receive(&small));
+    assert_eq!(dev.e1000_recv().unwrap(), core::slice::from_ref(&small));
+    assert_eq!(dev.e1000_transmit(&small), Ok(64));
+    assert_eq!(sim.take_transmitted().unwrap(), small);
+}
+
+#[test]
+fn mtu_change_drains_and_resets() {
+    let (mut dev, regs) = mock_device();
+    let mut kfn = SimKernelFunc;
+    let pkt = frame(60, 1);
+    let buf = tx_buffer(&mut kfn, &pkt);
+    assert_eq!(dev.e1000_transmit_zerocopy(buf), Ok(60));
+    assert_eq!(regs.read(E1000_TDT), 1);
+
+    // The mock never sends it, the ring is reset anyway and the buffer given back
+    assert_eq!(dev.set_mtu(9000), Ok(()));
+    assert_eq!(regs.read(E1000_TDT), 0);
+    assert_eq!(regs.read(E1000_TDH), 0);
+    assert_ne!(regs.read(E1000_TCTL) & E1000_TCTL_EN, 0);
+    assert_ne!(regs.read(E1000_RCTL) & E1000_RCTL_EN, 0);
+    assert_eq!(dev.e1000_tx_reclaim(), [buf]);
+}
+
+#[test]
+fn sim_mtu_change_keeps_settings() {
+    let (mut dev, sim) = sim_device();
+    let mac = [0x02, 0, 0, 0, 0, 1];
+    assert_eq!(dev.set_mac_address(mac), Ok(()));
+    assert_eq!(dev.add_unicast_filter(PEER_MAC, AddressSelect::Destination), Ok(1));
+    dev.set_vlan_offload(true);
+    dev.set_vlan_filtering(true);
+    assert_eq!(dev.vlan_filter_add(42), Ok(()));
+    dev.set_rx_checksum_offload(false);
+    assert_eq!(dev.force_speed_duplex(LinkSpeed::Mbps100, Duplex::Full), Ok(()));
+
+    assert_eq!(dev.set_mtu(9000), Ok(()));
+    assert_eq!(dev.unicast_filters().len(), 1);
+    assert!(dev.vlan_offload());
+    assert_ne!(sim.peek(E1000_RCTL) & E1000_RCTL_VFE, 0);
+    assert!(dev.vlan_filter_contains(42));
+    assert!(!dev.rx_checksum_offload());
+    assert_ne!(sim.peek(E1000_CTL) & E1000_CTL_FRCSPD, 0);
+
+    // Up again on the new mbufs
+    let jumbo = frame_to(mac, PEER_MAC, 9014);
+    assert!(sim.receive(&jumbo));
+    assert_eq!(dev.e1000_recv().unwrap(), core::slice::from_ref(&jumbo));
+    assert_eq!(dev.e1000_transmit(&jumbo), Ok(9014));
+    assert_eq!(sim.take_transmitted().unwrap(), jumbo);
+}
+
+#[test]
+fn invalid_mtu() {
+    let (mut dev, _sim) = sim_device();
+    assert_eq!(dev.set_mtu(67), Err(E1000Error::InvalidParam));
+    assert_eq!(dev.set_mtu(E1000_MAX_MTU + 1), Err(E1000Error::InvalidParam));
+    assert_eq!(dev.mtu(), 1500);
+    assert_eq!(dev.set_mtu(E1000_MAX_MTU), Ok(()));
+    assert_eq!(dev.max_frame_size(), E1000_MAX_MTU + 18);
+}
+
+#[test]
+fn transmit_scatter_gather() {
+    let (mut dev, regs) = mock_device();
+    let header = frame_to(PEER_MAC, SIM_MAC_ADDRESS, 14);
+    let ip = [0x45u8; 20];
+    let payload = [0x5au8; 1000];
+
+    // One descriptor per fragment, EOP on the last one only
+    assert_eq!(dev.e1000_transmit_sg(&[&header, &ip, &payload]), Ok(1034));
+    assert_eq!(regs.read(E1000_TDT), 3);
+    for (i, frag) in [&header[..], &ip, &payload].iter().enumerate() {
+        let desc = desc_addr(&regs, E1000_TDBAL, i);
+        let buf = dma_read::<u64>(desc) as usize;
+        assert_eq!(dma_read::<u16>(desc + 8) as usize, frag.len());
+        let eop = if i == 2 { E1000_TXD_CMD_EOP } else { 0 };
+        assert_eq!(dma_read::<u8>(desc + 11) as u32, E1000_TXD_CMD_RS | eop);
+        assert_eq!(dma_read::<u8>(buf), frag[0]);
+    }
+
+    // The whole packet must still fit in a frame
+    assert_eq!(dev.e1000_transmit_sg(&[&header, &[0; 1600]]), Err(E1000Error::FrameTooLarge));
+    assert_eq!(regs.read(E1000_TDT), 3);
+}
+
+#[test]
+fn sim_transmit_scatter_gather() {
+    let (mut dev, sim) = sim_device();
+    let header = frame_to(PEER_MAC, SIM_MAC_ADDRESS, 14);
+    let payload: Vec<u8> = (0..1400).map(|i| i as u8).collect();
+
+    assert_eq!(dev.e1000_transmit_sg(&[&header, &payload[..700], &payload[700..]]), Ok(1414));
+    assert_eq!(sim.take_transmitted().unwrap(), [&header[..], &payload].concat());
+    assert!(sim.take_transmitted().is_none());
+}
+
+/// A DMA buffer of the caller holding `packet`
+fn tx_buffer(kfn: &mut SimKernelFunc, packet: &[u8]) -> TxBuffer {
+    let (vaddr, dma) = kfn.dma_alloc_coherent(1);
+    unsafe { core::slice::from_raw_parts_mut(vaddr as *mut u8, packet.len()) }.copy_from_slice(packet);
+    TxBuffer { vaddr, dma, len: packet.len() }
+}
+
+#[test]
+fn transmit_zerocopy() {
+    let (mut dev, regs) = mock_device();
+    let mut kfn = SimKernelFunc;
+    let buf = tx_buffer(&mut kfn, &frame(60, 0x11));
+
+    // The descriptor points at the caller's buffer
+    assert_eq!(dev.e1000_transmit_zerocopy(buf), Ok(60));
+    let desc = desc_addr(&regs, E1000_TDBAL, 0);
+    assert_eq!(dma_read::<u64>(desc) as usize, buf.dma);
+    assert_eq!(dma_read::<u16>(desc + 8), 60);
+
+    // Lent until the hardware writes back DD
+    assert!(dev.e1000_tx_reclaim().is_empty());
+    dma_write(desc + 12, E1000_TXD_STAT_DD as u8);
+    assert_eq!(dev.e1000_tx_reclaim(), [buf]);
+    assert!(dev.e1000_tx_reclaim().is_empty());
+
+    // A reset gives back what is still lent
+    assert_eq!(dev.e1000_transmit_zerocopy(buf), Ok(60));
+    dev.e1000_init().unwrap();
+    assert_eq!(dev.e1000_tx_reclaim(), [buf]);
+
+    assert_eq!(dev.e1000_transmit_zerocopy(TxBuffer { len: 0, ..buf }), Err(E1000Error::InvalidParam));
+    kfn.dma_free_coherent(buf.vaddr, 1);
+}
+
+#[test]
+fn sim_transmit_zerocopy() {
+    let (mut dev, sim) = sim_device();
+    let mut kfn = SimKernelFunc;
+    let first = frame_to(PEER_MAC, SIM_MAC_ADDRESS, 100);
+    let second = frame_to(PEER_MAC, SIM_MAC_ADDRESS, 200);
+    let copied = frame_to(PEER_MAC, SIM_MAC_ADDRESS, 64);
+    let bufs = [tx_buffer(&mut kfn, &first), tx_buffer(&mut kfn, &second)];
+
+    assert_eq!(dev.e1000_transmit_zerocopy(bufs[0]), Ok(100));
+    assert_eq!(dev.e1000_transmit(&copied), Ok(64));
+    assert_eq!(dev.e1000_transmit_zerocopy(bufs[1]), Ok(200));
+    assert_eq!(sim.take_transmitted().unwrap(), first);
+    assert_eq!(sim.take_transmitted().unwrap(), copied);
+    assert_eq!(sim.take_transmitted().unwrap(), second);
+
+    assert_eq!(dev.e1000_tx_reclaim(), bufs);
+    for buf in bufs {
+        kfn.dma_free_coherent(buf.vaddr, 1);
+    }
+}
+
+#[test]
+fn recv_zerocopy() {
+    let mut kfn = SimKernelFunc;
+    let pool = RxPool::new(&mut kfn, 256 + 2, 2048).unwrap();
+    let sim = SimE1000::new();
+    let mut dev = E1000Device::with_regs(SimKernelFunc, sim.clone(), E1000Config::default()).unwrap();
+    assert_eq!(dev.e1000_attach_rx_pool(&pool), Ok(()));
+    assert_eq!(pool.available(), 2);
+
+    let frames: Vec<Vec<u8>> = (0..4).map(|i| frame_to(SIM_MAC_ADDRESS, PEER_MAC, 64 + i)).collect();
+    for frame in frames.iter() {
+        assert!(sim.receive(frame));
+    }
+
+    // Lent straight out of the DMA buffer, the descriptor refilled from the pool
+    let mut first = dev.e1000_recv_zerocopy().unwrap();
+    assert_eq!(&first[..], &frames[0][..]);
+    assert_eq!(first.meta().vlan_tag, None);
+    first[0] = 0xee;
+    let second = dev.e1000_recv_zerocopy().unwrap();
+    assert_eq!(&second[..], &frames[1][..]);
+    assert_eq!(pool.available(), 0);
+
+    // Nothing to refill with until a buffer comes back
+    assert!(dev.e1000_recv_zerocopy().is_none());
+    drop(first);
+    assert_eq!(pool.available(), 1);
+    assert_eq!(&dev.e1000_recv_zerocopy().unwrap()[..], &frames[2][..]);
+    // The copying path still works on pool buffers
+    assert_eq!(dev.e1000_recv().unwrap(), core::slice::from_ref(&frames[3]));
+    assert!(dev.e1000_recv_zerocopy().is_none());
+
+    drop(second);
+    dev.e1000_detach_rx_pool();
+    assert_eq!(pool.available(), pool.len());
+    assert!(sim.receive(&frames[0]));
+    assert_eq!(dev.e1000_recv().unwrap(), core::slice::from_ref(&frames[0]));
+    drop(dev);
+    pool.free(&mut kfn);
+}
+
+#[test]
+fn rx_pool_attach_checks() {
+    let mut kfn = SimKernelFunc;
+    let small = RxPool::new(&mut kfn, 8, 2048).unwrap();
+    let short = RxPool::new(&mut kfn, 300, 1024).unwrap();
+    let pool = RxPool::new(&mut kfn, 300, 2048).unwrap();
+    let mut dev = E1000Device::with_regs(SimKernelFunc, SimE1000::new(), E1000Config::default()).unwrap();
+
+    // Fewer buffers than descriptors, or buffers too small
+    assert_eq!(dev.e1000_attach_rx_pool(&small), Err(E1000Error::InvalidConfig));
+    assert_eq!(small.available(), 8);
+    assert_eq!(dev.e1000_attach_rx_pool(&short), Err(E1000Error::InvalidConfig));
+
+    assert_eq!(dev.e1000_attach_rx_pool(&pool), Ok(()));
+    assert_eq!(dev.e1000_attach_rx_pool(&pool), Err(E1000Error::InvalidConfig));
+    assert_eq!(dev.set_mtu(9000), Err(E1000Error::InvalidConfig));
+    dev.e1000_detach_rx_pool();
+    assert_eq!(dev.set_mtu(9000), Ok(()));
+    // Jumbo frames would span several buffers
+    assert_eq!(dev.e1000_attach_rx_pool(&pool), Err(E1000Error::InvalidConfig));
+
+    drop(dev);
+    for pool in [small, short, pool] {
+        pool.free(&mut kfn);
+    }
+}
+
+#[test]
+fn config_ring_sizes() {
+    let config = E1000Config::new().tx_ring_size(32).rx_ring_size(32).mbuf_size(1024);
+    let sim = SimE1000::new();
+    let mut dev = E1000Device::with_regs(SimKernelFunc, sim.clone(), config).unwrap();
+    assert_eq!(sim.peek(E1000_TDLEN), 32 * 16);
+    assert_eq!(sim.peek(E1000_RDLEN), 32 * 16);
+    assert_eq!(sim.peek(E1000_RDT), 31);
+    assert_eq!(sim.peek(E1000_RCTL) & E1000_RCTL_SZ_MASK, E1000_RCTL_SZ_1024);
+
+    // Both rings wrap around, frames beyond 1024 bytes spanning two buffers
+    for lap in 0..3 {
+        for i in 0..20 {
+            let pkt = frame(1500, (lap * 20 + i) as u8);
+            assert_eq!(dev.e1000_transmit(&pkt), Ok(1500));
+            assert_eq!(sim.take_transmitted().unwrap(), pkt);
+            assert!(sim.receive(&pkt));
+            assert_eq!(dev.e1000_recv().unwrap(), core::slice::from_ref(&pkt));
+        }
+    }
+
+    // The buffer size stays fixed across MTU changes
+    assert_eq!(dev.set_mtu(9000), Ok(()));
+    assert_eq!(sim.peek(E1000_RCTL) & E1000_RCTL_SZ_MASK, E1000_RCTL_SZ_1024);
+    let pkt = frame(9000, 7);
+    assert_eq!(dev.e1000_transmit(&pkt), Ok(9000));
+    assert_eq!(sim.take_transmitted().unwrap(), pkt);
+
+    let big = E1000Config::new().tx_ring_size(4096).rx_ring_size(4096);
+    let regs = MemRegs::new();
+    let _dev = E1000Device::with_regs(SimKernelFunc, regs.clone(), big).unwrap();
+    assert_eq!(regs.read(E1000_TDLEN), 4096 * 16);
+    assert_eq!(regs.read(E1000_RDT), 4095);
+}
+
+#[test]
+fn config_validation() {
+    assert_eq!(E1000Config::default().validate(), Ok(()));
+    for config in [
+        E1000Config::new().tx_ring_size(0),
+        E1000Config::new().tx_ring_size(30),
+        E1000Config::new().rx_ring_size(4104),
+        E1000Config::new().rx_ring_size(8192),
+        E1000Config::new().mbuf_size(3000),
+        E1000Config::new().mac_address([0x01, 0, 0x5e, 0, 0, 1]),
+    ] {
+        assert_eq!(config.validate(), Err(E1000Error::InvalidConfig));
+        assert!(E1000Device::with_regs(SimKernelFunc, MemRegs::new(), config).is_err());
+    }
+    assert_eq!(E1000Config::new().tx_ring_size(8).rx_ring_size(4096).validate(), Ok(()));
+}
+
+/// DMA memory that runs out after `budget` allocations, counting those not freed yet
+struct ScarceKernelFunc {
+    budget: usize,
+    live: Rc<Cell<usize>>,
+}
+
+impl KernelFunc for ScarceKernelFunc {
+    fn dma_alloc_coherent(&mut self, pages: usize) -> (usize, usize) {
+        if self.budget == 0 {
+            return (0, 0);
+        }
+        self.budget -= 1;
+        self.live.set(self.live.get() + 1);
+        SimKernelFunc.dma_alloc_coherent(pages)
+    }
+
+    fn dma_free_coherent(&mut self, vaddr: usize, pages: usize) {
+        self.live.set(self.live.get() - 1);
+        SimKernelFunc.dma_free_coherent(vaddr, pages)
+    }
+}
+
+/// Registers of a device stuck in reset
+struct StuckRegs(MemRegs);
+
+impl E1000Regs for StuckRegs {
+    fn read(&self, reg: usize) -> u32 {
+        let value = self.0.read(reg);
+        if reg == E1000_CTL {
+            value | E1000_CTL_RST
+        } else {
+            value
+        }
+    }
+
+    fn write(&mut self, reg: usize, value: u32) {
+        self.0.write(reg, value)
+    }
+}
+
+#[test]
+fn shutdown_gives_back_tx_buffers() {
+    let (mut dev, regs) = mock_device();
+    let mut kfn = SimKernelFunc;
+    let bufs: Vec<TxBuffer> = (0..3).map(|i| tx_buffer(&mut kfn, &frame(60, i))).collect();
+    for &buf in bufs.iter() {
+        assert_eq!(dev.e1000_transmit_zerocopy(buf), Ok(60));
+    }
+    // The first one sent but not reclaimed, the others never sent by the mock
+    dma_write(desc_addr(&regs, E1000_TDBAL, 0) + 12, E1000_TXD_STAT_DD as u8);
+    assert_eq!(dev.tx_clean().packets, 1);
+
+    assert_eq!(dev.shutdown(), bufs);
+    assert_eq!(regs.read(E1000_IMC), !0);
+    assert_eq!(regs.read(E1000_TCTL) & E1000_TCTL_EN, 0);
+}
+
+#[test]
+fn new_fails_without_dma_memory() {
+    // Rings and mbufs take four allocations, each of them may fail
+    for budget in 0..4 {
+        let live = Rc::new(Cell::new(0));
+        let kfn = ScarceKernelFunc { budget, live: live.clone() };
+        let dev = E1000Device::with_regs(kfn, MemRegs::new(), E1000Config::default());
+        assert_eq!(dev.err(), Some(E1000Error::AllocFailed), "budget {}", budget);
+        assert_eq!(live.get(), 0, "budget {}", budget);
+    }
+
+    let live = Rc::new(Cell::new(0));
+    let kfn = ScarceKernelFunc { budget: 4, live: live.clone() };
+    assert!(E1000Device::with_regs(kfn, MemRegs::new(), E1000Config::default()).is_ok());
+}
+
+#[test]
+fn new_fails_on_reset_timeout() {
+    let dev = E1000Device::with_regs(SimKernelFunc, StuckRegs(MemRegs::new()), E1000Config::default());
+    assert_eq!(dev.err(), Some(E1000Error::ResetTimeout));
+}
+
+#[test]
+fn drop_quiesces_and_frees() {
+    let live = Rc::new(Cell::new(0));
+    let kfn = ScarceKernelFunc { budget: usize::MAX, live: live.clone() };
+    let regs = MemRegs::new();
+    let mut dev = E1000Device::with_regs(kfn, regs.clone(), E1000Config::default()).unwrap();
+    // The larger mbufs of jumbo frames replace the first ones
+    dev.set_mtu(9000).unwrap();
+    assert_eq!(live.get(), 4);
+
+    drop(dev);
+    assert_eq!(live.get(), 0);
+    assert_eq!(regs.read(E1000_IMC), !0);
+    assert_eq!(regs.read(E1000_RCTL) & E1000_RCTL_EN, 0);
+    assert_eq!(regs.read(E1000_TCTL) & E1000_TCTL_EN, 0);
+
+    // Every failed open frees what it allocated as well, down to a device stuck in reset
+    let kfn = ScarceKernelFunc { budget: usize::MAX, live: live.clone() };
+    let dev = E1000Device::with_regs(kfn, StuckRegs(MemRegs::new()), E1000Config::default());
+    assert_eq!(dev.err(), Some(E1000Error::ResetTimeout));
+    assert_eq!(live.get(), 0);
+}
+
+#[test]
+fn drop_returns_rx_pool_buffers() {
+    let mut kfn = SimKernelFunc;
+    let pool = RxPool::new(&mut kfn, 300, 2048).unwrap();
+    let sim = SimE1000::new();
+    let mut dev = E1000Device::with_regs(SimKernelFunc, sim.clone(), E1000Config::default()).unwrap();
+    dev.e1000_attach_rx_pool(&pool).unwrap();
+    assert!(sim.receive(&frame(64, 1)));
+    let buf = dev.e1000_recv_zerocopy().unwrap();
+    assert_eq!(pool.available(), 300 - 256 - 1);
+
+    // A buffer lent out stays valid after the device is gone
+    drop(dev);
+    assert_eq!(pool.available(), 300 - 1);
+    assert_eq!(buf.len(), 64);
+    drop(buf);
+    assert_eq!(pool.available(), 300);
+    pool.free(&mut kfn);
+}
+
+#[test]
+fn tx_clean_counts_completions() {
+    let (mut dev, regs) = mock_device();
+    assert_eq!(dev.tx_clean(), TxCompletion::default());
+
+    // A jumbo frame over three descriptors, then a small one
+    dev.set_mtu(9000).unwrap();
+    assert_eq!(dev.e1000_transmit(&frame(9000, 1)), Ok(9000));
+    assert_eq!(dev.e1000_transmit(&frame(100, 2)), Ok(100));
+    assert_eq!(dev.tx_free_slots(), 255 - 4);
+
+    // Only whole packets count, in ring order
+    let dd = |i| dma_write(desc_addr(&regs, E1000_TDBAL, i) + 12, E1000_TXD_STAT_DD as u8);
+    dd(0);
+    dd(1);
+    assert_eq!(dev.tx_clean(), TxCompletion::default());
+    assert_eq!(dev.tx_free_slots(), 255 - 2);
+    dd(3);
+    assert_eq!(dev.tx_clean(), TxCompletion::default());
+    dd(2);
+    assert_eq!(dev.tx_clean(), TxCompletion { packets: 2, bytes: 9100 });
+    assert_eq!(dev.tx_free_slots(), 255);
+    assert_eq!(dev.tx_clean(), TxCompletion::default());
+}
+
+#[test]
+fn sim_tx_clean_after_wraparound() {
+    let (mut dev, sim) = sim_device();
+
+    let mut bytes = 0;
+    for i in 0..600 {
+        let pkt = frame(60 + i % 100, i as u8);
+        bytes += pkt.len();
+        assert_eq!(dev.e1000_transmit(&pkt), Ok(pkt.len()));
+        assert_eq!(sim.take_transmitted().unwrap(), pkt);
+    }
+    // Transmits short of room reclaimed some already, nothing is lost
+    assert_eq!(dev.tx_clean(), TxCompletion { packets: 600, bytes });
+    assert_eq!(dev.tx_free_slots(), 255);
+}
+
+#[test]
+fn update_stats_accumulates() {
+    let (mut dev, sim) = sim_device();
+
+    let pkt = frame(100, 1);
+    assert_eq!(dev.e1000_transmit(&pkt), Ok(pkt.len()));
+    assert!(sim.receive(&pkt));
+    let stats = *dev.update_stats();
+    assert_eq!((stats.gptc, stats.gotc, stats.bptc), (1, 104, 1));
+    assert_eq!((stats.gprc, stats.gorc, stats.bprc, stats.tpr, stats.tor), (1, 104, 1, 1, 104));
+    // The counters cleared on read, the totals go on
+    assert_eq!(sim.peek(E1000_GPRC), 0);
+    assert_eq!(sim.peek(E1000_GORCH), 0);
+    assert!(sim.receive(&pkt));
+    let stats = dev.update_stats();
+    assert_eq!((stats.gprc, stats.gorc, stats.gptc), (2, 208, 1));
+    assert_eq!(dev.hw_stats().mpc, 0);
+}
+
+#[test]
+fn update_stats_missed_packets() {
+    let (mut dev, sim) = sim_device();
+
+    let pkt = frame(60, 2);
+    let mut received = 0;
+    while sim.receive(&pkt) {
+        received += 1;
+    }
+    assert!(!sim.receive(&pkt));
+    let stats = dev.update_stats();
+    assert_eq!(stats.gprc, received);
+    assert_eq!((stats.mpc, stats.rnbc, stats.tpr), (2, 2, received + 2));
+    assert_eq!(stats.crcerrs, 0);
+}
+
+#[test]
+fn link_state_from_status() {
+    let (dev, mut regs) = mock_device();
+
+    assert!(!dev.link_state().up);
+    regs.write(E1000_STAT, E1000_STATUS_LU | E1000_STATUS_SPEED_100);
+    let expected = LinkState { up: true, speed: LinkSpeed::Mbps100, duplex: Duplex::Half };
+    assert_eq!(dev.link_state(), expected);
+    regs.write(E1000_STAT, E1000_STATUS_LU | E1000_STATUS_FD | E1000_STATUS_SPEED_1000);
+    let state = dev.link_state();
+    assert_eq!((state.speed.mbps(), state.duplex), (1000, Duplex::Full));
+}
+
+#[test]
+fn sim_link_change_events() {
+    let (mut dev, sim) = sim_device();
+    dev.e1000_irq_enable();
+
+    assert!(dev.last_link_state().up);
+    assert_eq!(dev.link_event(), None);
+
+    sim.set_link(false, 0);
+    assert!(sim.interrupt_pending());
+    assert!(dev.e1000_intr().contains(InterruptCause::LSC));
+    assert_eq!(dev.link_event(), Some(LinkState::default()));
+    assert_eq!(dev.link_event(), None);
+    assert!(!sim.receive(&frame(60, 1)));
+
+    sim.set_link(true, E1000_STATUS_SPEED_100);
+    dev.e1000_intr();
+    let expected = LinkState { up: true, speed: LinkSpeed::Mbps100, duplex: Duplex::Half };
+    assert_eq!(dev.link_event(), Some(expected));
+    assert_eq!(dev.last_link_state(), expected);
+    // A link status change without a change reports nothing
+    dev.e1000_cause_lsc_int();
+    dev.e1000_intr();
+    assert_eq!(dev.link_event(), None);
+}
+
+#[test]
+fn phy_register_access() {
+    let (mut dev, _sim) = sim_device();
+
+    let ctrl = dev.phy_read(PHY_CTRL).unwrap();
+    assert_ne!(ctrl & MII_CR_AUTO_NEG_EN, 0);
+    assert_eq!(dev.phy_autoneg_complete(), Ok(true));
+    assert_eq!(dev.phy_write(PHY_AUTONEG_ADV, 0x0181), Ok(()));
+    let modes = LinkModes::FULL_100 | LinkModes::HALF_100 | LinkModes::FULL_1000;
+    assert_eq!(dev.phy_advertised(), Ok(modes));
+    assert_eq!(dev.phy_read(E1000_PHY_REGS), Err(E1000Error::InvalidParam));
+
+    // No PHY answering MDIC
+    let (mut dev, _regs) = mock_device();
+    assert_eq!(dev.phy_read(PHY_STATUS), Err(E1000Error::PhyTimeout));
+}
+
+#[test]
+fn sim_autoneg_and_forced_speed() {
+    let (mut dev, sim) = sim_device();
+    let full_100 = LinkState { up: true, speed: LinkSpeed::Mbps100, duplex: Duplex::Full };
+
+    sim.set_link_partner(LinkModes::FULL_100 | LinkModes::HALF_100 | LinkModes::PAUSE);
+    dev.e1000_intr();
+    assert_eq!(dev.link_event(), Some(full_100));
+    assert_eq!(
+        dev.phy_partner_abilities(),
+        Ok(LinkModes::FULL_100 | LinkModes::HALF_100 | LinkModes::PAUSE)
+    );
+
+    // Nothing in common with the partner
+    assert_eq!(dev.phy_advertise(LinkModes::FULL_10 | LinkModes::HALF_10), Ok(()));
+    dev.e1000_intr();
+    assert_eq!(dev.link_event().map(|state| state.up), Some(false));
+    assert_eq!(dev.phy_advertise(LinkModes::PAUSE), Err(E1000Error::InvalidParam));
+
+    assert_eq!(dev.force_speed_duplex(LinkSpeed::Mbps100, Duplex::Full), Ok(()));
+    dev.e1000_intr();
+    assert_eq!(dev.link_event(), Some(full_100));
+    let forced = E1000_CTL_FRCSPD | E1000_CTL_FRCDPLX | E1000_CTL_SLU | E1000_CTL_FD;
+    let ctl = sim.peek(E1000_CTL);
+    assert_eq!(ctl & forced, forced);
+    assert_eq!(ctl & E1000_CTL_SPD_SEL, E1000_CTL_SPD_100);
+    assert_eq!(dev.phy_read(PHY_CTRL).unwrap() & MII_CR_AUTO_NEG_EN, 0);
+    assert_eq!(
+        dev.force_speed_duplex(LinkSpeed::Mbps1000, Duplex::Full),
+        Err(E1000Error::InvalidParam)
+    );
+
+    // Back to auto-negotiation of every speed
+    assert_eq!(dev.phy_advertise(LinkModes::ALL_SPEEDS), Ok(()));
+    assert_eq!(sim.peek(E1000_CTL) & (E1000_CTL_FRCSPD | E1000_CTL_FRCDPLX), 0);
+    assert_eq!(dev.link_state(), full_100);
+}
+
+#[test]
+fn sim_forced_speed_takes_a_phy_reset() {
+    let (mut dev, sim) = sim_device();
+    sim.set_link_partner(LinkModes::FULL_100 | LinkModes::HALF_100);
+    dev.e1000_intr();
+    assert_eq!(dev.link_event().map(|state| state.speed), Some(LinkSpeed::Mbps100));
+
+    // Without a reset the PHY keeps the negotiated mode
+    assert_eq!(dev.phy_write(PHY_CTRL, MII_CR_FULL_DUPLEX), Ok(()));
+    dev.e1000_intr();
+    assert_eq!(dev.link_event(), None);
+
+    assert_eq!(dev.force_speed_duplex(LinkSpeed::Mbps10, Duplex::Full), Ok(()));
+    assert_eq!(dev.phy_read(PHY_CTRL).unwrap() & MII_CR_RESET, 0);
+    dev.e1000_intr();
+    assert_eq!(
+        dev.link_event(),
+        Some(LinkState { up: true, speed: LinkSpeed::Mbps10, duplex: Duplex::Full })
+    );
+}
+
+/// MDIC completing every access with an error
+struct MdicErrorRegs(MemRegs);
+
+impl E1000Regs for MdicErrorRegs {
+    fn read(&self, reg: usize) -> u32 {
+        let value = self.0.read(reg);
+        if reg == E1000_MDIC {
+            value | E1000_MDIC_READY | E1000_MDIC_ERROR
+        } else {
+            value
+        }
+    }
+
+    fn write(&mut self, reg: usize, value: u32) {
+        self.0.write(reg, value)
+    }
+}
+
+#[test]
+fn phy_access_reports_mdic_error() {
+    let regs = MdicErrorRegs(MemRegs::new());
+    let mut dev = E1000Device::with_regs(SimKernelFunc, regs, E1000Config::default()).unwrap();
+    assert_eq!(dev.phy_read(PHY_STATUS), Err(E1000Error::PhyError));
+    assert_eq!(dev.phy_write(PHY_AUTONEG_ADV, 0), Err(E1000Error::PhyError));
+    assert_eq!(
+        dev.force_speed_duplex(LinkSpeed::Mbps100, Duplex::Full),
+        Err(E1000Error::PhyError)
+    );
+}
+
+#[test]
+fn sim_forced_speed_back_to_autoneg() {
+    let (mut dev, sim) = sim_device();
+    sim.set_link_partner(LinkModes::FULL_100 | LinkModes::HALF_100);
+    dev.e1000_intr();
+
+    assert_eq!(dev.force_speed_duplex(LinkSpeed::Mbps10, Duplex::Half), Ok(()));
+    dev.e1000_intr();
+    assert_eq!(dev.link_state().speed, LinkSpeed::Mbps10);
+    assert_eq!(sim.peek(E1000_CTL) & E1000_CTL_ASDE, 0);
+
+    // The MAC follows the PHY again, which negotiates 100 Mb/s full duplex
+    assert_eq!(dev.phy_restart_autoneg(), Ok(()));
+    let ctl = sim.peek(E1000_CTL);
+    assert_eq!(ctl & (E1000_CTL_FRCSPD | E1000_CTL_FRCDPLX), 0);
+    assert_eq!(ctl & E1000_CTL_ASDE, E1000_CTL_ASDE);
+    assert_ne!(dev.phy_read(PHY_CTRL).unwrap() & MII_CR_AUTO_NEG_EN, 0);
+    dev.e1000_intr();
+    assert_eq!(
+        dev.link_event(),
+        Some(LinkState { up: true, speed: LinkSpeed::Mbps100, duplex: Duplex::Full })
+    );
+}
+
+#[test]
+fn flow_control_negotiation() {
+    let (mut dev, sim) = sim_device();
+    assert_eq!(dev.flow_control(), FlowControl::None);
+    assert_eq!(sim.peek(E1000_FCT), 0x8808);
+
+    let config = FlowControlConfig::new(FlowControl::Full)
+        .pause_time(0x100)
+        .discard_pause_frames(true);
+    assert_eq!(dev.set_flow_control(config), Ok(()));
+    assert_eq!(sim.peek(E1000_FCAL), 0x00C28001);
+    assert_eq!(sim.peek(E1000_FCTTV), 0x100);
+    // 48 KB receive buffer, XOFF at 90%
+    let high = (48 * 1024 * 9 / 10) & 0xfff8;
+    assert_eq!(sim.peek(E1000_FCRTH), high);
+    assert_eq!(sim.peek(E1000_FCRTL), E1000_FCRTL_XONE | (high - 8));
+    assert_ne!(sim.peek(E1000_RCTL) & E1000_RCTL_DPF, 0);
+    assert!(dev.phy_advertised().unwrap().contains(LinkModes::PAUSE | LinkModes::ASYM_PAUSE));
+
+    // The partner can't do PAUSE
+    dev.e1000_intr();
+    assert_eq!(dev.flow_control(), FlowControl::None);
+
+    sim.set_link_partner(LinkModes::ALL_SPEEDS | LinkModes::PAUSE);
+    dev.e1000_intr();
+    assert_eq!(dev.flow_control(), FlowControl::Full);
+    let fc_bits = E1000_CTL_RFCE | E1000_CTL_TFCE;
+    assert_eq!(sim.peek(E1000_CTL) & fc_bits, fc_bits);
+
+    // Only able to send PAUSE frames, the partner honors them
+    sim.set_link_partner(LinkModes::ALL_SPEEDS | LinkModes::ASYM_PAUSE);
+    dev.e1000_intr();
+    assert_eq!(dev.flow_control(), FlowControl::RxPause);
+    // Advertising other speeds keeps the PAUSE abilities
+    assert_eq!(dev.phy_advertise(LinkModes::FULL_100), Ok(()));
+    dev.e1000_intr();
+    assert_eq!(dev.flow_control(), FlowControl::RxPause);
+}
+
+#[test]
+fn flow_control_forced_and_invalid() {
+    let (mut dev, sim) = sim_device();
+
+    assert_eq!(dev.force_speed_duplex(LinkSpeed::Mbps100, Duplex::Full), Ok(()));
+    dev.e1000_intr();
+    let config = FlowControlConfig::new(FlowControl::TxPause)
+        .watermarks(0x4000, 0x8000)
+        .send_xon(false);
+    assert_eq!(dev.set_flow_control(config), Ok(()));
+    assert_eq!(dev.flow_control(), FlowControl::TxPause);
+    assert_eq!((sim.peek(E1000_FCRTL), sim.peek(E1000_FCRTH)), (0x4000, 0x8000));
+
+    // No PAUSE frames in half duplex
+    assert_eq!(dev.force_speed_duplex(LinkSpeed::Mbps10, Duplex::Half), Ok(()));
+    dev.e1000_intr();
+    assert_eq!(dev.flow_control(), FlowControl::None);
+
+    let config = FlowControlConfig::new(FlowControl::Full).watermarks(0x8000, 0x4000);
+    assert_eq!(dev.set_flow_control(config), Err(E1000Error::InvalidConfig));
+    assert_eq!(dev.set_flow_control(FlowControlConfig::new(FlowControl::None)), Ok(()));
+    assert_eq!((sim.peek(E1000_FCRTL), sim.peek(E1000_FCRTH)), (0, 0));
+}
+
+#[test]
+fn coalesce_registers() {
+    let (mut dev, regs) = mock_device();
+    assert_eq!((regs.read(E1000_RDTR), regs.read(E1000_ITR)), (0, 0));
+
+    let config = CoalesceConfig::new()
+        .rx_delay(8)
+        .rx_abs_delay(32)
+        .tx_delay(16)
+        .tx_abs_delay(64)
+        .itr(Itr::Fixed(8000));
+    assert_eq!(dev.set_coalesce(config), Ok(()));
+    assert_eq!(regs.read(E1000_RDTR), 8);
+    assert_eq!(regs.read(E1000_RADV), 32);
+    assert_eq!(regs.read(E1000_TIDV), 16);
+    assert_eq!(regs.read(E1000_TADV), 64);
+    // 8000 interrupts/s, in units of 256 ns
+    assert_eq!(regs.read(E1000_ITR), 488);
+    assert_eq!(dev.itr_rate(), 8000);
+
+    // TIDV only delays descriptors with IDE
+    let pkt = frame(60, 1);
+    assert_eq!(dev.e1000_transmit(&pkt), Ok(pkt.len()));
+    let desc = desc_addr(&regs, E1000_TDBAL, 0);
+    assert_ne!(dma_read::<u8>(desc + 11) as u32 & E1000_TXD_CMD_IDE, 0);
+
+    assert_eq!(dev.set_coalesce(config.itr(Itr::Fixed(10))), Err(E1000Error::InvalidConfig));
+    assert_eq!(dev.coalesce(), config);
+}
+
+#[test]
+fn sim_adaptive_itr() {
+    let (mut dev, sim) = sim_device();
+    assert_eq!(dev.set_coalesce(CoalesceConfig::new().itr(Itr::Adaptive)), Ok(()));
+    assert_eq!(dev.itr_rate(), 20000);
+
+    // Bulk traffic of full-sized frames
+    for i in 0..40 {
+        assert!(sim.receive(&frame(1514, i)));
+    }
+    assert_eq!(dev.e1000_recv().map(|packets| packets.len()), Some(40));
+    dev.e1000_intr();
+    assert_eq!(dev.itr_rate(), 4000);
+    assert_eq!(sim.peek(E1000_ITR), 1_000_000_000 / (4000 * 256));
+
+    // A trickle of small packets, the rate rises in steps up to what the idle tx side allows
+    let mut rates = Vec::new();
+    for i in 0..6 {
+        assert!(sim.receive(&frame(60, i)));
+        dev.e1000_recv();
+        dev.e1000_intr();
+        rates.push(dev.itr_rate());
+    }
+    assert_eq!(rates, [9000, 14000, 19000, 20000, 20000, 20000]);
+
+    // Below 1000 Mb/s it stays at 4000
+    sim.set_link(true, E1000_STATUS_SPEED_100 | E1000_STATUS_FD);
+    dev.e1000_intr();
+    assert_eq!(dev.itr_rate(), 4000);
+}
+
+#[test]
+fn interrupt_mask_chosen_by_caller() {
+    let (mut dev, mut regs) = mock_device();
+    assert_eq!(dev.interrupt_mask(), InterruptCause::DEFAULT);
+
+    dev.enable_interrupts(InterruptCause::TXQE | InterruptCause::RXO);
+    assert_eq!(regs.read(E1000_IMS), E1000_IMS_TXQE | E1000_IMS_RXO);
+    dev.disable_interrupts(InterruptCause::LSC);
+    assert_eq!(regs.read(E1000_IMC), E1000_IMS_LSC);
+    let mask = InterruptCause::DEFAULT & !InterruptCause::LSC | InterruptCause::TXQE | InterruptCause::RXO;
+    assert_eq!(dev.interrupt_mask(), mask);
+
+    dev.e1000_irq_enable();
+    assert_eq!(regs.read(E1000_IMS), mask.bits());
+    // Bits besides the known causes are acknowledged but not reported
+    regs.write(E1000_ICR, E1000_ICR_RXSEQ | 1 << 9);
+    assert_eq!(dev.e1000_intr(), InterruptCause::RXSEQ);
+    assert_eq!(!InterruptCause::ALL, InterruptCause::empty());
+}
+
+#[test]
+fn sim_tx_done_interrupt() {
+    let (mut dev, sim) = sim_device();
+    // Opt-in, nothing pending for a sent packet by default
+    assert_eq!(dev.e1000_transmit(&frame(60, 3)), Ok(60));
+    assert!(!sim.interrupt_pending());
+    assert!(dev.e1000_intr().contains(InterruptCause::TXDW));
+    assert_eq!(dev.tx_clean().packets, 1);
+
+    dev.disable_interrupts(InterruptCause::ALL);
+    dev.enable_interrupts(InterruptCause::TXDW);
+
+    assert!(sim.receive(&frame(60, 1)));
+    assert!(!sim.interrupt_pending());
+    let pkt = frame(60, 2);
+    assert_eq!(dev.e1000_transmit(&pkt), Ok(pkt.len()));
+    assert!(sim.interrupt_pending());
+    let cause = dev.e1000_intr();
+    assert!(cause.contains(InterruptCause::TXDW | InterruptCause::RXT0));
+    assert!(!sim.interrupt_pending());
+    assert_eq!(dev.tx_clean().packets, 1);
+}
+
+#[test]
+fn poll_rx_budget() {
+    let (mut dev, sim) = sim_device();
+    let frames: Vec<Vec<u8>> = (0..5).map(|i| frame(60 + i, i as u8)).collect();
+    for f in frames.iter() {
+        assert!(sim.receive(f));
+    }
+
+    let mut received = Vec::new();
+    assert!(dev.poll_rx(0, |frame, _| received.push(frame.to_vec())));
+    assert!(received.is_empty());
+    assert!(dev.poll_rx(2, |frame, _| received.push(frame.to_vec())));
+    assert_eq!(received.len(), 2);
+    assert!(!dev.poll_rx(64, |frame, meta| {
+        assert_eq!(meta.vlan_tag, None);
+        received.push(frame.to_vec());
+    }));
+    assert_eq!(received, frames);
+    assert!(!dev.poll_rx(64, |_, _| panic!("no frame left")));
+}
+
+#[test]
+fn poll_rx_budget_in_frames() {
+    let sim = SimE1000::new();
+    let config = E1000Config::new().mbuf_size(512);
+    let mut dev = E1000Device::with_regs(SimKernelFunc, sim.clone(), config).unwrap();
+    let small = frame(60, 1);
+    let large = frame(1514, 2);
+    assert!(sim.receive(&small));
+    assert!(sim.receive(&large));
+    assert!(sim.receive(&small));
+
+    // A frame counts once, however many descriptors it spans
+    let mut received = Vec::new();
+    assert!(dev.poll_rx(1, |frame, _| received.push(frame.to_vec())));
+    assert!(dev.poll_rx(1, |frame, _| received.push(frame.to_vec())));
+    assert_eq!(received, [small.clone(), large.clone()]);
+    // No budget, no frame
+    assert!(dev.poll_rx(0, |frame, _| received.push(frame.to_vec())));
+    assert!(!dev.poll_rx(5, |frame, _| received.push(frame.to_vec())));
+    assert_eq!(received, [small.clone(), large, small]);
+    assert!(!dev.poll_rx(0, |frame, _| received.push(frame.to_vec())));
+}
@@ -0,0 +1,65 @@
+// Transmit completion: software head/tail of the tx ring, descriptors reclaimed once
+// the hardware wrote back DD [E1000 3.4]
+use super::e1000::{E1000Device, KernelFunc};
+use super::e1000_const::*;
+use super::regs::E1000Regs;
+use core::mem::take;
+
+/// Packets and bytes the hardware finished sending, e.g. for `netdev_completed_queue`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TxCompletion {
+    pub packets: usize,
+    pub bytes: usize,
+}
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Reclaim the descriptors the hardware is done with.
+    /// Returns what completed since the last call, including descriptors
+    /// reclaimed meanwhile by a transmit short of room.
+    pub fn tx_clean(&mut self) -> TxCompletion {
+        self.e1000_tx_reap();
+        take(&mut self.tx_completed)
+    }
+
+    /// Number of descriptors free for transmitting, as of the last reclaim.
+    /// A packet takes one per mbuf it spans, plus one for a new offload context.
+    pub fn tx_free_slots(&self) -> usize {
+        let ring_len = self.tx_ring.len();
+        let in_flight = (self.tx_next_to_use + ring_len - self.tx_next_to_clean) % ring_len;
+        // TDT catching up with TDH would mean an empty ring
+        ring_len - 1 - in_flight
+    }
+
+    /// Advance the software head over the descriptors with DD written back,
+    /// counting the packets ending there and returning lent buffers
+    pub(super) fn e1000_tx_reap(&mut self) {
+        let ring_len = self.tx_ring.len();
+        while self.tx_next_to_clean != self.tx_next_to_use {
+            let index = self.tx_next_to_clean;
+            if self.tx_ring[index].status & E1000_TXD_STAT_DD as u8 == 0 {
+                break;
+            }
+            if let Some(len) = self.tx_packet_len[index].take() {
+                self.tx_completed.packets += 1;
+                self.tx_completed.bytes += len;
+                self.itr.tx_packets += 1;
+                self.itr.tx_bytes += len;
+            }
+            self.e1000_tx_return(index);
+            self.tx_next_to_clean = (index + 1) % ring_len;
+        }
+    }
+
+    /// Empty the tx ring, TDH and TDT back to 0. Packets in flight are dropped
+    /// without being counted as completed; lent buffers are given back.
+    pub(super) fn e1000_tx_ring_reset(&mut self) {
+        self.regs.write(E1000_TDT, 0); // TX Desc Tail
+        self.regs.write(E1000_TDH, 0); // TX Desc Head
+        self.tx_next_to_use = 0;
+        self.tx_next_to_clean = 0;
+        for index in 0..self.tx_ring.len() {
+            self.tx_packet_len[index] = None;
+            self.e1000_tx_return(index);
+        }
+    }
+}
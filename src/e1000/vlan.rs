@@ -0,0 +1,82 @@
+// 802.1Q VLAN: tag insertion, stripping and the VLAN filter table [E1000 3.2.3, 3.3.3, 13.4.1]
+use super::e1000::{E1000Device, KernelFunc};
+use super::e1000_const::*;
+use super::error::E1000Error;
+use super::regs::E1000Regs;
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Insert tags on transmit and strip them on receive (CTRL.VME)
+    pub fn set_vlan_offload(&mut self, enable: bool) {
+        let ctl = self.regs.read(E1000_CTL);
+        if enable {
+            self.regs.write(E1000_CTL, ctl | E1000_CTL_VME);
+        } else {
+            self.regs.write(E1000_CTL, ctl & !E1000_CTL_VME);
+        }
+        self.e1000_write_flush();
+    }
+
+    /// Whether tag insertion and stripping are enabled
+    pub fn vlan_offload(&self) -> bool {
+        self.regs.read(E1000_CTL) & E1000_CTL_VME != 0
+    }
+
+    /// Transmitting a network packet, the hardware inserts the 802.1Q tag `vlan_tag`
+    /// (PCP, DEI, VLAN ID) after the source address.
+    /// Needs `set_vlan_offload(true)`.
+    pub fn e1000_transmit_vlan(&mut self, packet: &[u8], vlan_tag: u16) -> Result<usize, E1000Error> {
+        if !self.vlan_offload() {
+            error!("e1000, VLAN tag insertion is not enabled");
+            return Err(E1000Error::InvalidConfig);
+        }
+        self.e1000_xmit(&[packet], E1000_TXD_CMD_VLE, vlan_tag)
+    }
+
+    /// Drop tagged packets whose VLAN ID isn't in the filter table (RCTL.VFE)
+    pub fn set_vlan_filtering(&mut self, enable: bool) {
+        let rctl = self.regs.read(E1000_RCTL);
+        if enable {
+            self.regs.write(E1000_RCTL, rctl | E1000_RCTL_VFE);
+        } else {
+            self.regs.write(E1000_RCTL, rctl & !E1000_RCTL_VFE);
+        }
+        self.e1000_write_flush();
+    }
+
+    fn e1000_vfta_set(&mut self, vid: u16, set: bool) -> Result<(), E1000Error> {
+        if vid > VLAN_VID_MASK {
+            error!("e1000, invalid VLAN ID {}", vid);
+            return Err(E1000Error::InvalidParam);
+        }
+        let reg = E1000_VFTA + (vid as usize >> 5);
+        let bit = 1 << (vid & 0x1F);
+        let vfta = self.regs.read(reg);
+        self.regs.write(reg, if set { vfta | bit } else { vfta & !bit });
+        self.e1000_write_flush();
+        Ok(())
+    }
+
+    /// Accept packets of VLAN `vid` while filtering
+    pub fn vlan_filter_add(&mut self, vid: u16) -> Result<(), E1000Error> {
+        self.e1000_vfta_set(vid, true)
+    }
+
+    /// Drop packets of VLAN `vid` while filtering
+    pub fn vlan_filter_remove(&mut self, vid: u16) -> Result<(), E1000Error> {
+        self.e1000_vfta_set(vid, false)
+    }
+
+    /// Whether VLAN `vid` is in the filter table
+    pub fn vlan_filter_contains(&self, vid: u16) -> bool {
+        vid <= VLAN_VID_MASK
+            && self.regs.read(E1000_VFTA + (vid as usize >> 5)) & (1 << (vid & 0x1F)) != 0
+    }
+
+    /// Empty the VLAN filter table
+    pub fn vlan_filter_clear(&mut self) {
+        for i in 0..E1000_VLAN_FILTER_TBL_SIZE {
+            self.regs.write(E1000_VFTA + i, 0);
+        }
+        self.e1000_write_flush();
+    }
+}
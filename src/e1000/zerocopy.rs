@@ -0,0 +1,67 @@
+// Zero-copy transmit: descriptors pointing straight at buffers of the caller
+use super::e1000::{E1000Device, KernelFunc};
+use super::e1000_const::*;
+use super::error::E1000Error;
+use super::regs::E1000Regs;
+use super::super::Ext;
+use alloc::vec::Vec;
+use core::mem::take;
+
+/// A DMA-able buffer of the caller holding a whole packet, lent to the hardware
+/// by `e1000_transmit_zerocopy` and given back by `e1000_tx_reclaim`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxBuffer {
+    /// CPU virtual address
+    pub vaddr: usize,
+    /// DMA address the hardware reads the packet from
+    pub dma: usize,
+    /// Length of the packet
+    pub len: usize,
+}
+
+impl<'a, K: KernelFunc, R: E1000Regs> E1000Device<'a, K, R> {
+    /// Transmitting a network packet without copying it into an mbuf.
+    /// The buffer must stay valid, and its content unchanged, until `e1000_tx_reclaim`
+    /// returns it; if the packet isn't queued the buffer is still the caller's.
+    pub fn e1000_transmit_zerocopy(&mut self, buf: TxBuffer) -> Result<usize, E1000Error> {
+        if buf.len > self.max_frame_size() {
+            error!("The packet: {} to be send is TOO LARGE", buf.len);
+            return Err(E1000Error::FrameTooLarge);
+        }
+        if buf.dma == 0 || buf.len == 0 {
+            error!("e1000, invalid zero-copy buffer {:x?}", buf);
+            return Err(E1000Error::InvalidParam);
+        }
+        let tindex = self.e1000_tx_reserve(1)?;
+
+        info!(">>>>>>>>> TX PKT {} (zero-copy)", buf.len);
+        let ide = self.e1000_tx_cmd_ide();
+        let desc = &mut self.tx_ring[tindex];
+        desc.addr = buf.dma as u64;
+        desc.length = buf.len as u16;
+        desc.cso = 0;
+        desc.css = 0;
+        desc.status = 0;
+        desc.cmd = (E1000_TXD_CMD_RS | E1000_TXD_CMD_EOP | ide) as u8;
+        desc.special = 0;
+        self.tx_loans[tindex] = Some(buf);
+
+        self.e1000_tx_kick((tindex + 1) % self.tx_ring.len(), buf.len);
+
+        Ok(buf.len)
+    }
+
+    /// Give back the zero-copy buffers the hardware is done with, oldest first.
+    /// Reclaims the completed descriptors, which `tx_clean` reports.
+    pub fn e1000_tx_reclaim(&mut self) -> Vec<TxBuffer> {
+        self.e1000_tx_reap();
+        take(&mut self.tx_done)
+    }
+
+    /// Move the buffer lent for descriptor `index`, if any, to the completion queue
+    pub(super) fn e1000_tx_return(&mut self, index: usize) {
+        if let Some(buf) = self.tx_loans[index].take() {
+            self.tx_done.push(buf);
+        }
+    }
+}
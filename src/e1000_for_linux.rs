@@ -1,11 +1,13 @@
 //! Rust e1000 network device.
 #![allow(unused)]
 
+use core::cmp::{max, min};
 use core::slice::from_raw_parts_mut;
 use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
 use kernel::prelude::*;
+use kernel::error::code::{EBUSY, EINVAL, EIO, ENOENT, ENOMEM, ENOSPC, ETIMEDOUT};
 use kernel::{
-    bindings, c_str, define_pci_id_table, device, dma, driver,
+    bindings, c_str, define_pci_id_table, device::{self, RawDevice}, dma, driver,
     file::{self, File},
     io_buffer::{IoBufferReader, IoBufferWriter},
     irq,
@@ -41,6 +43,13 @@ pub trait Ext<T> {
     fn to_vec(&self) -> Vec<T> {
         unimplemented!()
     }
+    /// Vec function extend_from_slice
+    fn extend_from_slice(&mut self, other: &[T])
+    where
+        T: Clone,
+    {
+        unimplemented!()
+    }
 }
 
 impl<T> Ext<T> for Vec<T> {
@@ -51,6 +60,13 @@ impl<T> Ext<T> for Vec<T> {
     fn push(&mut self, value: T) {
         self.try_push(value);
     }
+
+    fn extend_from_slice(&mut self, other: &[T])
+    where
+        T: Clone,
+    {
+        self.try_extend_from_slice(other).unwrap();
+    }
 }
 
 impl<T: Clone> Ext<T> for [T] {
@@ -60,6 +76,8 @@ impl<T: Clone> Ext<T> for [T] {
 }
 
 const RXBUFFER: u32 = 2048;
+/// Tx descriptors a frame takes, start_xmit sending it in place from one
+const TX_DESCS_PER_FRAME: usize = 1;
 /// Intel E1000 ID
 const VENDOR_ID_INTEL: u32 = 0x8086;
 const DEVICE_ID_INTEL_I219: u32 = 0x15fc;
@@ -67,6 +85,7 @@ const DEVICE_ID_INTEL_82540EM: u32 = 0x100e;
 const DEVICE_ID_INTEL_82574L: u32 = 0x10d3;
 //const MAC_HWADDR: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
 //const MAC_HWADDR: [u8; 6] = [0x90, 0xe2, 0xfc, 0xb5, 0x36, 0x95];
+/// Fallback MAC address, if the EEPROM can't give the permanent one
 const MAC_HWADDR: [u8; 6] = [0x52, 0x54, 0x00, 0x6c, 0xf8, 0x88];
 
 module! {
@@ -80,46 +99,53 @@ module! {
 struct E1000Driver;
 
 impl E1000Driver {
-    fn handle_rx_irq(dev: &net::Device, napi: &Napi, data: &NetData) {
-        let mut packets = 0;
+    /// Receive up to `budget` packets, returning how many were taken off the ring
+    /// and whether more are left on it
+    fn handle_rx_irq(
+        dev: &net::Device,
+        napi: &Napi,
+        data: &NetData,
+        budget: usize,
+    ) -> (usize, bool) {
         let mut bytes = 0;
+        let mut frames = 0;
 
-        let recv_vec = {
+        // Copy the frames into skbs with the device locked, hand them up afterwards
+        let mut skbs = Vec::new();
+        let more = {
             let mut dev_e1k = data.dev_e1000.lock_irqdisable();
-            dev_e1k.as_mut().unwrap().e1000_recv()
-        };
-
-        if let Some(vec) = recv_vec {
-            packets = vec.len();
-            for (_i, packet) in vec.iter().enumerate() {
-                let mut len = packet.len();
-                //bytes += len;
-
-                let skb = dev.alloc_skb_ip_align(RXBUFFER).unwrap();
+            dev_e1k.as_mut().unwrap().poll_rx(budget, |packet, _meta| {
+                frames += 1;
+                let len = packet.len();
+                let skb = match dev.alloc_skb_ip_align(max(RXBUFFER, len as u32)) {
+                    Ok(skb) => skb,
+                    Err(_) => {
+                        pr_warn!("e1000, no skb for a received packet, dropped\n");
+                        return;
+                    }
+                };
                 let skb_buf =
                     unsafe { from_raw_parts_mut(skb.head_data().as_ptr() as *mut u8, len) };
-                skb_buf.copy_from_slice(&packet);
-
-                //len -= 4; // ?
+                skb_buf.copy_from_slice(packet);
                 skb.put(len as u32);
-                let protocol = skb.eth_type_trans(dev);
-                skb.protocol_set(protocol);
-
-                // Send the skb up the stack
-                napi.gro_receive(&skb);
-
                 bytes += len;
+                skbs.push(skb);
+            })
+        };
 
-                //pr_info!("Sk Buff protocol: {:#x}, [01] = {:x}{:x}\n", protocol, skb_buf[0], skb_buf[1]);
-            }
-            info!(
-                "handle_rx_irq, received packets: {}, bytes: {}\n",
-                packets,
-                bytes
-            );
-        } else {
-            pr_warn!("None packets were received\n");
+        let packets = skbs.len();
+        for skb in skbs.iter() {
+            let protocol = skb.eth_type_trans(dev);
+            skb.protocol_set(protocol);
+
+            // Send the skb up the stack
+            napi.gro_receive(skb);
         }
+        info!(
+            "handle_rx_irq, received packets: {}, bytes: {}\n",
+            packets,
+            bytes
+        );
 
         data.stats
             .rx_bytes
@@ -127,10 +153,67 @@ impl E1000Driver {
         data.stats
             .rx_packets
             .fetch_add(packets as u64, Ordering::Relaxed);
+        (frames, more)
     }
 
-    fn handle_tx_irq() {
-        // check status E1000_TXD_STAT_DD
+    fn handle_tx_irq(dev: &net::Device, data: &NetData, budget: i32) {
+        // Descriptors with E1000_TXD_STAT_DD written back, and the skbs they sent
+        let (done, bufs) = {
+            let mut dev_e1k = data.dev_e1000.lock_irqdisable();
+            let dev_e1k = dev_e1k.as_mut().unwrap();
+            let done = (dev_e1k.tx_clean(), dev_e1k.e1000_tx_reclaim());
+            // Room again for the queue start_xmit stopped, decided under the same lock
+            if dev_e1k.tx_free_slots() >= TX_DESCS_PER_FRAME {
+                dev.netif_wake_queue();
+            }
+            done
+        };
+        if !bufs.is_empty() {
+            let mut sent = Vec::new();
+            {
+                let mut tx_skbs = data.tx_skbs.lock_irqdisable();
+                for buf in bufs.iter() {
+                    match tx_skbs.iter().position(|tx| tx.dma == buf.dma) {
+                        Some(i) => sent.push(tx_skbs.swap_remove(i)),
+                        None => pr_warn!("e1000, no skb sent from {:#x}\n", buf.dma),
+                    }
+                }
+            }
+            // Freed with the lock dropped, as NAPI expects
+            for tx in sent.iter() {
+                tx.free(&data.dev, budget);
+            }
+        }
+        if done.packets == 0 {
+            return;
+        }
+
+        data.stats.tx_bytes.fetch_add(done.bytes as u64, Ordering::Relaxed);
+        data.stats.tx_packets.fetch_add(done.packets as u64, Ordering::Relaxed);
+        dev.completed_queue(done.packets as u32, done.bytes as u32);
+    }
+
+    /// Follow the link with the carrier, after a link status change interrupt
+    fn handle_link_change(dev: &net::Device, data: &NetData) {
+        let event = {
+            let mut dev_e1k = data.dev_e1000.lock_irqdisable();
+            dev_e1k.as_mut().unwrap().link_event()
+        };
+        match event {
+            Some(state) if state.up => {
+                pr_info!(
+                    "e1000 link up, {} Mb/s {:?} duplex\n",
+                    state.speed.mbps(),
+                    state.duplex
+                );
+                dev.netif_carrier_on();
+            }
+            Some(_) => {
+                pr_info!("e1000 link down\n");
+                dev.netif_carrier_off();
+            }
+            None => {}
+        }
     }
 }
 
@@ -155,9 +238,9 @@ impl irq::Handler for E1000Driver {
             bindings::readl(ptr as *const u32 as _)
         };
         */
-        info!("irq::Handler E1000_ICR = {:#x}\n", intr);
+        info!("irq::Handler E1000_ICR = {:#x}\n", intr.bits());
 
-        if (intr & (1<<7)) == 0 {
+        if !intr.intersects(InterruptCause::RXT0 | InterruptCause::LSC | InterruptCause::TXDW) {
             pr_warn!("No valid e1000 interrupt was found\n");
             return irq::Return::None;
         }
@@ -183,11 +266,19 @@ impl NapiPoller for Poller {
     fn poll(napi: &Napi, budget: i32, dev: &net::Device, data: &NetData) -> i32 {
         info!("NapiPoller poll\n");
 
-        E1000Driver::handle_rx_irq(dev, napi, data);
-        E1000Driver::handle_tx_irq();
+        E1000Driver::handle_link_change(dev, data);
+        E1000Driver::handle_tx_irq(dev, data, budget);
+        let budget = max(budget, 0) as usize;
+        let (work_done, _more) = E1000Driver::handle_rx_irq(dev, napi, data, budget);
+        let work_done = min(work_done, budget);
 
-        napi.complete_done(1);
-        1
+        // Packets are left only with the budget used up, NAPI then polls again
+        // without being completed
+        if work_done == budget {
+            return budget as i32;
+        }
+        napi.complete_done(work_done as i32);
+        work_done as i32
     }
 }
 
@@ -218,16 +309,20 @@ impl<T> e1000::KernelFunc for Kernfn<T> {
     const PAGE_SIZE: usize = 1 << 12;
 
     fn dma_alloc_coherent(&mut self, pages: usize) -> (usize, usize) {
-        let alloc = dma::Allocation::<T>::try_new(
+        let alloc = match dma::Allocation::<T>::try_new(
             &*self.dev,
             pages * Self::PAGE_SIZE,
             bindings::GFP_KERNEL,
-        )
-        .unwrap();
+        ) {
+            Ok(alloc) => alloc,
+            Err(_) => return (0, 0),
+        };
 
         let vaddr = alloc.cpu_addr as usize;
         let paddr = alloc.dma_handle as usize;
-        self.alloc_coherent.try_push(alloc);
+        if self.alloc_coherent.try_push(alloc).is_err() {
+            return (0, 0);
+        }
         pr_info!("Allocated {} pages, vaddr: {:#x}, paddr: {:#x}\n", pages, vaddr, paddr);
 
         (vaddr, paddr)
@@ -246,6 +341,72 @@ impl<T> e1000::KernelFunc for Kernfn<T> {
     }
 }
 
+/// Map `len` bytes at `vaddr` for the device to read, returning the DMA address or 0
+fn dma_map_to_device(dev: &device::Device, vaddr: usize, len: usize) -> usize {
+    let raw = dev.raw_device();
+    let dma = unsafe {
+        bindings::dma_map_single_attrs(
+            raw,
+            vaddr as *mut _,
+            len,
+            bindings::dma_data_direction_DMA_TO_DEVICE,
+            0,
+        )
+    };
+    if unsafe { bindings::dma_mapping_error(raw, dma) } != 0 {
+        return 0;
+    }
+    dma as usize
+}
+
+/// Undo `dma_map_to_device`
+fn dma_unmap_to_device(dev: &device::Device, dma: usize, len: usize) {
+    unsafe {
+        bindings::dma_unmap_single_attrs(
+            dev.raw_device(),
+            dma as _,
+            len,
+            bindings::dma_data_direction_DMA_TO_DEVICE,
+            0,
+        )
+    };
+}
+
+/// An skb whose data the hardware reads in place, from `start_xmit` until
+/// `handle_tx_irq` gets its buffer back
+struct TxSkb {
+    skb: *const SkBuff,
+    dma: usize,
+    len: usize,
+}
+
+impl TxSkb {
+    /// Unmap the data and free the skb
+    fn free(&self, dev: &device::Device, budget: i32) {
+        dma_unmap_to_device(dev, self.dma, self.len);
+        unsafe { &*self.skb }.napi_consume(budget);
+    }
+}
+
+impl From<E1000Error> for Error {
+    fn from(err: E1000Error) -> Self {
+        match err {
+            E1000Error::AllocFailed => ENOMEM,
+            E1000Error::RingFull => EBUSY,
+            E1000Error::FilterFull => ENOSPC,
+            E1000Error::NotFound => ENOENT,
+            E1000Error::ResetTimeout | E1000Error::PhyTimeout => ETIMEDOUT,
+            E1000Error::LinkDown
+            | E1000Error::EepromTimeout
+            | E1000Error::EepromChecksum
+            | E1000Error::PhyError => EIO,
+            E1000Error::FrameTooLarge | E1000Error::InvalidConfig | E1000Error::InvalidParam => {
+                EINVAL
+            }
+        }
+    }
+}
+
 unsafe impl Send for NetData {}
 unsafe impl Sync for NetData {}
 
@@ -253,6 +414,10 @@ struct NetData {
     dev: Arc<device::Device>,
     res: Arc<MappedResource>,
     dev_e1000: Arc<SpinLock<Option<E1000Device<'static, Kernfn<u8>>>>>,
+    /// The skbs the hardware is sending, one per tx descriptor at most
+    tx_skbs: Arc<SpinLock<Vec<TxSkb>>>,
+    /// The address given to the netdev, for RA[0] even if the EEPROM failed
+    mac_addr: [u8; 6],
     stats: Stats64,
     napi: Arc<net::Napi>,
     irq: Option<u32>,
@@ -273,7 +438,12 @@ impl net::DeviceOperations for E1000Driver {
             alloc_coherent: Vec::new(),
         };
         let regs = data.res.ptr;
-        let mut e1000_device = E1000Device::<Kernfn<u8>>::new(kfn, regs).unwrap();
+        let config = E1000Config::new().mac_address(data.mac_addr);
+        let mut e1000_device = E1000Device::<Kernfn<u8>>::new(kfn, regs, config)
+            .map_err(|err| {
+                pr_err!("Failed to initialize the e1000 device: {}\n", err);
+                Error::from(err)
+            })?;
 
         pr_info!("e1000 device is initialized\n");
         {
@@ -296,11 +466,22 @@ impl net::DeviceOperations for E1000Driver {
 
         dev.netif_start_queue();
 
-        {
+        let link = {
             let mut dev_e1k = data.dev_e1000.lock_irqdisable();
             let e1k_fn = dev_e1k.as_mut().unwrap();
 
-            // enable rx int
+            // Delays and adaptive ITR as Linux e1000 sets them by default
+            let coalesce = CoalesceConfig::new()
+                .rx_abs_delay(8)
+                .tx_delay(8)
+                .tx_abs_delay(32)
+                .itr(Itr::Adaptive);
+            if let Err(err) = e1k_fn.set_coalesce(coalesce) {
+                pr_warn!("e1000, interrupt moderation not set: {}\n", err);
+            }
+
+            // enable rx int, and tx completions to clean the ring and report BQL
+            e1k_fn.enable_interrupts(InterruptCause::TXDW);
             e1k_fn.e1000_irq_enable();
 
             /* fire a link status change interrupt to start the watchdog */
@@ -311,11 +492,16 @@ impl net::DeviceOperations for E1000Driver {
                 bindings::writel(4, ptr as _);
             }
             */
+            e1k_fn.link_state()
         };
 
-        // watchdog handler ?
-        // Enable net interface
-        dev.netif_carrier_on();
+        // The carrier follows the link, link changes are taken in poll
+        if link.up {
+            dev.netif_carrier_on();
+        } else {
+            pr_info!("e1000 link is down\n");
+            dev.netif_carrier_off();
+        }
 
         Ok(())
     }
@@ -339,29 +525,48 @@ impl net::DeviceOperations for E1000Driver {
             _size
         );
 
-        dev.sent_queue(skb.len());
+        // The hardware reads the packet straight from the skb, kept until handle_tx_irq
+        let vaddr = skb_data.as_ptr() as usize;
+        let len = skb_data.len();
+        let dma = dma_map_to_device(&data.dev, vaddr, len);
+        if dma == 0 {
+            pr_warn!("e1000, failed to map the skbuff for DMA, dropped\n");
+            skb.napi_consume(64);
+            return net::NetdevTx::Ok;
+        }
 
-        let len = {
+        let res = {
             let mut dev_e1k = data.dev_e1000.lock_irqdisable();
-            dev_e1k.as_mut().unwrap().e1000_transmit(skb_data)
+            let dev_e1k = dev_e1k.as_mut().unwrap();
+            let res = dev_e1k.e1000_transmit_zerocopy(TxBuffer { vaddr, dma, len });
+            if let Ok(sent) = res {
+                // Recorded before the device is unlocked, so handle_tx_irq finds it.
+                // No allocation: there is room for a whole ring.
+                let skb = skb as *const SkBuff;
+                data.tx_skbs.lock().push(TxSkb { skb, dma, len });
+                // Queued for BQL before tx_clean, under the same lock, can report
+                // it completed; as the same bytes tx_clean reports
+                dev.sent_queue(sent as u32);
+            }
+            // No requeueing while the ring is full, handle_tx_irq wakes the queue
+            if dev_e1k.tx_free_slots() < TX_DESCS_PER_FRAME {
+                dev.netif_stop_queue();
+            }
+            res
         };
 
-        if len < 0 {
-            pr_warn!("Failed to send transmit the skbuff packet: {}", len);
-            return net::NetdevTx::Busy;
-        }
-
-        // when clean_tx_irq
-        {
-            let bytes = skb.len() as u64;
-            let packets = 1;
-
-            skb.napi_consume(64);
-
-            data.stats.tx_bytes.fetch_add(bytes, Ordering::Relaxed);
-            data.stats.tx_packets.fetch_add(packets, Ordering::Relaxed);
-
-            dev.completed_queue(packets as u32, bytes as u32);
+        match res {
+            Ok(_) => {}
+            Err(E1000Error::RingFull) => {
+                dma_unmap_to_device(&data.dev, dma, len);
+                return net::NetdevTx::Busy;
+            }
+            Err(err) => {
+                // Retrying won't help, drop the packet
+                pr_warn!("Failed to send transmit the skbuff packet: {}\n", err);
+                dma_unmap_to_device(&data.dev, dma, len);
+                skb.napi_consume(64);
+            }
         }
 
         net::NetdevTx::Ok
@@ -381,16 +586,29 @@ impl net::DeviceOperations for E1000Driver {
     fn stop(dev: &Device, data: <Self::Data as ForeignOwnable>::Borrowed<'_>) -> Result {
         pr_info!("net::DeviceOperations::stop\n");
         dev.netif_carrier_off();
-        let mut dev_e1k = data.dev_e1000.lock_irqdisable();
-        dev_e1k.as_mut().unwrap().e1000_irq_disable();
+        {
+            let mut dev_e1k = data.dev_e1000.lock_irqdisable();
+            dev_e1k.as_mut().unwrap().e1000_irq_disable();
+        }
 
         dev.netif_stop_queue();
         data.napi.disable();
 
-        let irq_ptr = data.irq_handler.load(Ordering::Relaxed);
+        let irq_ptr = data.irq_handler.swap(core::ptr::null_mut(), Ordering::Relaxed);
         unsafe{ drop(Box::from_raw(irq_ptr)); }
 
-        drop(data);
+        // Shutting the device down stops the NIC and frees its DMA memory, open allocates
+        // it anew. The buffers it gives back are those of tx_skbs, freed below.
+        let e1000_device = data.dev_e1000.lock_irqdisable().take();
+        let _unsent = e1000_device.map(|e1000_device| e1000_device.shutdown());
+
+        // What the stopped NIC never sent
+        let tx_skbs = core::mem::take(&mut *data.tx_skbs.lock_irqdisable());
+        for tx in tx_skbs.iter() {
+            tx.free(&data.dev, 0);
+        }
+        // Their bytes are no longer in flight for BQL
+        dev.reset_queue();
         Ok(())
     }
 }
@@ -434,9 +652,18 @@ impl pci::Driver for E1000Driver {
         dma::set_mask(pci_dev, 0xffffffff)?;
         dma::set_coherent_mask(pci_dev, 0xffffffff)?;
 
+        let mac_addr = match e1000::read_mac_address(&mut MmioRegs::new(bar_res.ptr)) {
+            Ok(mac) => mac,
+            Err(err) => {
+                pr_warn!("Failed to read MAC address from EEPROM: {}, use {:x?}\n", err, MAC_HWADDR);
+                MAC_HWADDR
+            }
+        };
+        pr_info!("e1000 MAC address: {:x?}\n", mac_addr);
+
         let mut regist = net::Registration::<E1000Driver>::try_new(pci_dev)?;
         let net_dev = regist.dev_get();
-        net_dev.eth_hw_addr_set(&MAC_HWADDR);
+        net_dev.eth_hw_addr_set(&mac_addr);
         let dev = Arc::try_new(device::Device::from_dev(pci_dev))?;
         let bar_res = Arc::try_new(bar_res)?;
         /*
@@ -450,6 +677,14 @@ impl pci::Driver for E1000Driver {
         );
         let mut dev_e1000 = Arc::try_new(lock_e1000)?;
 
+        let mut lock_tx_skbs =
+            unsafe { SpinLock::new(Vec::try_with_capacity(E1000_DEFAULT_RING_SIZE)?) };
+        spinlock_init!(
+            unsafe { Pin::new_unchecked(&mut lock_tx_skbs) },
+            "e1000_tx_skbs"
+        );
+        let tx_skbs = Arc::try_new(lock_tx_skbs)?;
+
         let napi = net::NapiAdapter::<Poller>::add_weight(&net_dev, 64)?;
         net_dev.netif_carrier_off();
 
@@ -457,6 +692,8 @@ impl pci::Driver for E1000Driver {
             dev,
             res: bar_res.clone(),
             dev_e1000,
+            tx_skbs,
+            mac_addr,
             stats: Stats64::new(),
             napi: napi.into(),
             irq,
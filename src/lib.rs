@@ -1,5 +1,7 @@
 #![no_std]
 #![allow(unused)]
+// `div_ceil` and `is_multiple_of` are newer than the pinned toolchain
+#![allow(unknown_lints, clippy::manual_div_ceil, clippy::manual_is_multiple_of)]
 
 extern crate alloc;
 
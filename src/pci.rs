@@ -9,6 +9,7 @@ pub const E1000_REGS: u32 = 0x40000000;
 pub const ECAM: u32 = 0x30000000;
 
 // Simple PCI-E Scanning for qemu and its e1000 ethernet
+#[allow(clippy::identity_op)]
 pub fn pci_init() {
     // Look at each PCI device on bus 0
     for dev in 0..32 {